@@ -1,4 +1,4 @@
-use crate::{logger, models::GuillotineSpecificConfig, utils::constants};
+use crate::{constants, logger, models::GuillotineSpecificConfig};
 
 use cli_table::{
 	format::{
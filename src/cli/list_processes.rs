@@ -1,4 +1,4 @@
-use crate::{logger, models::GuillotineSpecificConfig, utils::constants};
+use crate::{constants, logger, models::GuillotineSpecificConfig};
 
 use cli_table::{
 	format::{
@@ -68,6 +68,8 @@ pub async fn list_processes(config: GuillotineSpecificConfig) {
 		Cell::new("Uptime", header_format),
 		Cell::new("Crashes", header_format),
 		Cell::new("Created at", header_format),
+		Cell::new("Next run", header_format),
+		Cell::new("Last run", header_format),
 	])];
 	for process in processes.iter() {
 		let process = process.as_object().unwrap();
@@ -102,6 +104,30 @@ pub async fn list_processes(config: GuillotineSpecificConfig) {
 						.foreground_color(Some(Color::Red))
 						.build(),
 				),
+				"waiting" => Cell::new(
+					"waiting",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Yellow))
+						.build(),
+				),
+				"completed" => Cell::new(
+					"completed",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Green))
+						.build(),
+				),
+				"failed" => Cell::new(
+					"failed",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Red))
+						.build(),
+				),
+				"scheduled" => Cell::new(
+					"scheduled",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Yellow))
+						.build(),
+				),
 				_ => Cell::new(
 					"unknown",
 					CellFormat::builder()
@@ -159,6 +185,28 @@ pub async fn list_processes(config: GuillotineSpecificConfig) {
 				),
 				Default::default(),
 			),
+			Cell::new(
+				&match process.get("nextRun").unwrap().as_number() {
+					Some(next_run) => super::get_date_time(next_run.as_i64().unwrap()),
+					None => String::from("-"),
+				},
+				Default::default(),
+			),
+			match process.get("lastRun").unwrap().as_string() {
+				Some(last_run) if last_run == "succeeded" => Cell::new(
+					last_run,
+					CellFormat::builder()
+						.foreground_color(Some(Color::Green))
+						.build(),
+				),
+				Some(last_run) => Cell::new(
+					last_run,
+					CellFormat::builder()
+						.foreground_color(Some(Color::Red))
+						.build(),
+				),
+				None => Cell::new("-", Default::default()),
+			},
 		]));
 	}
 	let table = Table::new(table_data, table_format);
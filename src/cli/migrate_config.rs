@@ -0,0 +1,117 @@
+use crate::{
+	constants, logger,
+	models::parser::{self, ConfigFormat},
+};
+
+use async_std::{fs, path::Path};
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Map, Value};
+
+type Migration = fn(&mut Map<String, Value>);
+
+// Each migration upgrades a config from one version to the next
+const MIGRATIONS: [(&str, &str, Migration); 1] = [("1.0.0", "1.1.0", migrate_1_0_0)];
+
+pub async fn migrate_config(config_path: &Path, contents: String) {
+	let file = config_path.to_string_lossy().to_string();
+	let format = ConfigFormat::from_path(config_path);
+
+	let mut config = match parser::parse::<Value>(&contents, format) {
+		Ok(Value::Object(config)) => config,
+		Ok(_) => {
+			logger::error(&format!("{}: Expected the config to be an object", file));
+			std::process::exit(1);
+		}
+		Err(err) => {
+			logger::error(&format!("{}", err.in_file(&file)));
+			std::process::exit(1);
+		}
+	};
+	let old_version = match config.get("version") {
+		Some(Value::String(version)) => version.clone(),
+		_ => {
+			logger::error(&format!("{}: version: Expected a string", file));
+			std::process::exit(1);
+		}
+	};
+	if old_version == constants::CONFIG_VERSION {
+		logger::info(&format!(
+			"{} is already at version {}",
+			file,
+			constants::CONFIG_VERSION
+		));
+		return;
+	}
+
+	let mut version = old_version.clone();
+	for (from, to, migration) in MIGRATIONS.iter() {
+		if version == *from {
+			migration(&mut config);
+			version = to.to_string();
+			config.insert(String::from("version"), Value::String(version.clone()));
+		}
+	}
+	if version != constants::CONFIG_VERSION {
+		logger::error(&format!(
+			"{}: Don't know how to migrate a config at version '{}'",
+			file, old_version
+		));
+		std::process::exit(1);
+	}
+
+	let migrated = match serialize(&Value::Object(config), format) {
+		Ok(migrated) => migrated,
+		Err(err) => {
+			logger::error(&format!("Error writing the migrated config: {}", err));
+			std::process::exit(1);
+		}
+	};
+
+	let backup_path = format!("{}.{}.bak", file, old_version);
+	if let Err(err) = fs::copy(config_path, &backup_path).await {
+		logger::error(&format!(
+			"Error backing up {} to {}: {}",
+			file, backup_path, err
+		));
+		std::process::exit(1);
+	}
+	if let Err(err) = fs::write(config_path, migrated).await {
+		logger::error(&format!("Error writing {}: {}", file, err));
+		std::process::exit(1);
+	}
+	logger::info(&format!(
+		"Migrated {} from version {} to {}. The old config is at {}",
+		file, old_version, version, backup_path
+	));
+}
+
+// Before 1.1.0, the other configs were ignored when there was a base config.
+// They're now merged into it, so drop them to keep the config doing the same thing
+fn migrate_1_0_0(config: &mut Map<String, Value>) {
+	if config.contains_key("config") && config.remove("configs").is_some() {
+		logger::warn(
+			"Removed `configs`, since they were ignored in favour of `config` before 1.1.0",
+		);
+	}
+}
+
+fn serialize(config: &Value, format: ConfigFormat) -> Result<String, String> {
+	match format {
+		ConfigFormat::Json => {
+			let mut output = vec![];
+			let mut serializer = serde_json::Serializer::with_formatter(
+				&mut output,
+				PrettyFormatter::with_indent(b"\t"),
+			);
+			config
+				.serialize(&mut serializer)
+				.map_err(|err| err.to_string())?;
+			output.push(b'\n');
+			String::from_utf8(output).map_err(|err| err.to_string())
+		}
+		ConfigFormat::Yaml => serde_yaml::to_string(config).map_err(|err| err.to_string()),
+		ConfigFormat::Toml => toml::Value::try_from(config)
+			.and_then(|config| toml::to_string_pretty(&config))
+			.map_err(|err| err.to_string()),
+	}
+}
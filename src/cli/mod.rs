@@ -1,16 +1,33 @@
 mod get_module_info;
 mod list_modules;
 mod list_processes;
+mod migrate_config;
+mod reload_process;
 mod restart_process;
+mod run;
+mod scale_process;
+mod schema;
+mod validate;
+mod watch_events;
 
 pub use get_module_info::get_module_info;
 pub use list_modules::list_modules;
 pub use list_processes::list_processes;
+pub use migrate_config::migrate_config;
+pub use reload_process::reload_process;
 pub use restart_process::restart_process;
+pub use run::run;
+pub use scale_process::scale_process;
+pub use schema::print_schema;
+pub use validate::validate;
+pub use watch_events::watch_events;
 
 use chrono::{prelude::*, Utc};
 
-pub async fn on_exit() {}
+pub async fn on_exit() {
+	run::on_exit().await;
+	watch_events::on_exit();
+}
 
 fn get_date_time(timestamp: i64) -> String {
 	Utc.timestamp_millis(timestamp)
@@ -0,0 +1,83 @@
+use crate::{constants, logger, models::GuillotineSpecificConfig};
+
+use async_std::task;
+use clap::ArgMatches;
+use juno::{models::Value, JunoModule};
+use std::{collections::HashMap, time::Duration};
+
+pub async fn reload_process(config: GuillotineSpecificConfig, args: &ArgMatches<'_>) {
+	let mut module = if config.juno.connection_type == "unix_socket" {
+		let socket_path = config.juno.socket_path.as_ref().unwrap();
+		JunoModule::from_unix_socket(socket_path)
+	} else {
+		let port = config.juno.port.as_ref().unwrap();
+		let bind_addr = config.juno.bind_addr.as_ref().unwrap();
+		JunoModule::from_inet_socket(bind_addr, *port)
+	};
+	let name = args.value_of("name").unwrap();
+
+	module
+		.initialize(
+			&format!("{}-cli", constants::APP_NAME),
+			constants::APP_VERSION,
+			HashMap::new(),
+		)
+		.await
+		.unwrap();
+
+	let response = module
+		.call_function(&format!("{}.reloadProcess", constants::APP_NAME), {
+			let mut map = HashMap::new();
+			map.insert(String::from("name"), Value::String(String::from(name)));
+			map
+		})
+		.await
+		.unwrap();
+
+	if !response.is_object() {
+		logger::error(&format!("Expected object response. Got {:?}", response));
+		return;
+	}
+	let response = response.as_object().unwrap();
+
+	let success = response.get("success").unwrap();
+	if !success.as_bool().unwrap() {
+		let error = response.get("error").unwrap().as_string().unwrap();
+		logger::error(&format!("Error reloading process: {}", error));
+		return;
+	}
+	logger::info(&format!("Reloading '{}'", name));
+
+	// Wait for every instance to be replaced
+	loop {
+		task::sleep(Duration::from_millis(500)).await;
+		let processes = module
+			.call_function(
+				&format!("{}.listProcesses", constants::APP_NAME),
+				HashMap::new(),
+			)
+			.await
+			.unwrap();
+		if !processes.is_array() {
+			logger::error(&format!("Expected array response. Got {:?}", processes));
+			return;
+		}
+		let is_reloading = processes.as_array().unwrap().iter().any(|process| {
+			let process = process.as_object().unwrap();
+			process
+				.get("name")
+				.unwrap()
+				.as_string()
+				.unwrap()
+				.split(':')
+				.next() == Some(name)
+				&& process.get("reloading").unwrap().as_bool() == Some(&true)
+		});
+		if !is_reloading {
+			break;
+		}
+	}
+	drop(module);
+
+	logger::info(&format!("Finished reloading '{}'", name));
+}
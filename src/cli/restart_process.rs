@@ -1,4 +1,4 @@
-use crate::{logger, models::GuillotineSpecificConfig, utils::constants};
+use crate::{constants, logger, models::GuillotineSpecificConfig};
 
 use clap::ArgMatches;
 use cli_table::{
@@ -144,6 +144,30 @@ pub async fn restart_process(config: GuillotineSpecificConfig, args: &ArgMatches
 						.foreground_color(Some(Color::Red))
 						.build(),
 				),
+				"scheduled" => Cell::new(
+					"scheduled",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Yellow))
+						.build(),
+				),
+				"waiting" => Cell::new(
+					"waiting",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Yellow))
+						.build(),
+				),
+				"completed" => Cell::new(
+					"completed",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Green))
+						.build(),
+				),
+				"failed" => Cell::new(
+					"failed",
+					CellFormat::builder()
+						.foreground_color(Some(Color::Red))
+						.build(),
+				),
 				_ => Cell::new(
 					"unknown",
 					CellFormat::builder()
@@ -0,0 +1,25 @@
+use crate::{logger, models::GuillotineSpecificConfig};
+
+use clap::ArgMatches;
+use guillotine::Supervisor;
+use std::sync::Mutex;
+
+lazy_static! {
+	static ref SUPERVISOR: Mutex<Option<Supervisor>> = Mutex::new(None);
+}
+
+pub async fn run(config: GuillotineSpecificConfig, args: &ArgMatches<'_>) {
+	let supervisor = Supervisor::new(config).watch_all(args.is_present("watch"));
+	*SUPERVISOR.lock().unwrap() = Some(supervisor.clone());
+	if let Err(err) = supervisor.run().await {
+		logger::error(&err);
+	}
+}
+
+// Stops the modules, if they're being run
+pub async fn on_exit() {
+	let supervisor = SUPERVISOR.lock().unwrap().take();
+	if let Some(supervisor) = supervisor {
+		supervisor.stop().await;
+	}
+}
@@ -0,0 +1,64 @@
+use crate::{constants, logger, models::GuillotineSpecificConfig};
+
+use clap::ArgMatches;
+use juno::{
+	models::{Number, Value},
+	JunoModule,
+};
+use std::collections::HashMap;
+
+pub async fn scale_process(config: GuillotineSpecificConfig, args: &ArgMatches<'_>) {
+	let mut module = if config.juno.connection_type == "unix_socket" {
+		let socket_path = config.juno.socket_path.as_ref().unwrap();
+		JunoModule::from_unix_socket(socket_path)
+	} else {
+		let port = config.juno.port.as_ref().unwrap();
+		let bind_addr = config.juno.bind_addr.as_ref().unwrap();
+		JunoModule::from_inet_socket(bind_addr, *port)
+	};
+	let name = args.value_of("name").unwrap();
+	let instances = match args.value_of("instances").unwrap().parse::<u64>() {
+		Ok(instances) => instances,
+		Err(_) => {
+			logger::error("Instances supplied is not a number!");
+			return;
+		}
+	};
+
+	module
+		.initialize(
+			&format!("{}-cli", constants::APP_NAME),
+			constants::APP_VERSION,
+			HashMap::new(),
+		)
+		.await
+		.unwrap();
+
+	let response = module
+		.call_function(&format!("{}.scaleProcess", constants::APP_NAME), {
+			let mut map = HashMap::new();
+			map.insert(String::from("name"), Value::String(String::from(name)));
+			map.insert(
+				String::from("instances"),
+				Value::Number(Number::PosInt(instances)),
+			);
+			map
+		})
+		.await
+		.unwrap();
+	drop(module);
+
+	if !response.is_object() {
+		logger::error(&format!("Expected object response. Got {:?}", response));
+		return;
+	}
+	let response = response.as_object().unwrap();
+
+	let success = response.get("success").unwrap();
+	if !success.as_bool().unwrap() {
+		let error = response.get("error").unwrap().as_string().unwrap();
+		logger::error(&format!("Error scaling process: {}", error));
+		return;
+	}
+	logger::info(&format!("Scaled '{}' to {} instance(s)", name, instances));
+}
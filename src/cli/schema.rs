@@ -0,0 +1,25 @@
+use crate::{
+	logger,
+	models::{GuillotineConfig, ModuleRunnerConfig},
+};
+
+use clap::ArgMatches;
+use schemars::schema_for;
+
+pub fn print_schema(args: &ArgMatches<'_>) {
+	let schema = match args.value_of("kind").unwrap_or("config") {
+		"config" => schema_for!(GuillotineConfig),
+		"module" => schema_for!(ModuleRunnerConfig),
+		kind => {
+			logger::error(&format!(
+				"Unknown schema '{}'. Expected 'config' or 'module'",
+				kind
+			));
+			return;
+		}
+	};
+	match serde_json::to_string_pretty(&schema) {
+		Ok(schema) => println!("{}", schema),
+		Err(err) => logger::error(&format!("Error generating the schema: {}", err)),
+	}
+}
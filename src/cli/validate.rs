@@ -0,0 +1,147 @@
+use crate::{
+	logger,
+	models::{discovery, parser, ConfigError, ModuleRunnerConfig},
+};
+
+use async_std::path::Path;
+use guillotine::{check_dependencies, check_module_config, uses_secrets_store};
+
+pub async fn validate(config_path: &Path, contents: String, profile: Option<&str>) {
+	let file = config_path.to_string_lossy().to_string();
+	let mut errors = vec![];
+	let mut module_count = 0;
+	let mut module_names = vec![];
+	let mut declared_modules: Vec<(ModuleRunnerConfig, Source)> = vec![];
+
+	let config = parser::read_config(config_path, &contents)
+		.and_then(|(config, files)| parser::select_env_config(config, files, profile));
+	match config {
+		Ok((config, sources)) => {
+			let has_secrets_store = config.secrets_store.is_some();
+			for err in parser::check_config(&config).await {
+				errors.push(sources.locate(err).in_file(&file));
+			}
+
+			if let Some(modules) = &config.modules {
+				let discovery =
+					discovery::discover_modules(modules, config.overrides.as_ref()).await;
+				for (dir, reason) in discovery.skipped.iter() {
+					logger::info(&format!("Skipping '{}': {}", dir.to_string_lossy(), reason));
+				}
+				for name in discovery.unused_overrides.iter() {
+					logger::warn(&format!(
+						"Not overriding '{}', since there's no module by that name to run",
+						name
+					));
+				}
+				for err in discovery.errors {
+					let err = if err.file.is_some() {
+						err
+					} else {
+						sources.locate(err).in_file(&file)
+					};
+					errors.push(err);
+				}
+				for module in discovery.modules {
+					module_count += 1;
+					let manifest_file = module.manifest_path.to_string_lossy();
+					if let Some(err) = check_name(&mut module_names, &module.config.name) {
+						errors.push(err.in_file(&manifest_file));
+					}
+					let working_dir = match &module.config.cwd {
+						Some(cwd) => module.dir.join(cwd),
+						None => module.dir.clone(),
+					};
+					for err in check_module_config(&module.config, &working_dir.to_string_lossy()) {
+						errors.push(err.in_file(&manifest_file));
+					}
+					if let Some(err) = check_secrets_store(&module.config, has_secrets_store) {
+						errors.push(err.in_file(&manifest_file));
+					}
+					declared_modules
+						.push((module.config, Source::Manifest(manifest_file.to_string())));
+				}
+			}
+
+			for (index, process) in config.processes.iter().flatten().enumerate() {
+				if !process.enabled.unwrap_or(true) {
+					continue;
+				}
+				module_count += 1;
+				if let Some(err) = check_name(&mut module_names, &process.name) {
+					let err = err.with_prefix(&format!("processes[{}]", index));
+					errors.push(sources.locate(err).in_file(&file));
+				}
+				if let Some(err) = check_secrets_store(process, has_secrets_store) {
+					let err = err.with_prefix(&format!("processes[{}]", index));
+					errors.push(sources.locate(err).in_file(&file));
+				}
+				if let Some(cwd) = &process.cwd {
+					for err in check_module_config(process, cwd) {
+						let err = err.with_prefix(&format!("processes[{}]", index));
+						errors.push(sources.locate(err).in_file(&file));
+					}
+				}
+				declared_modules.push((process.clone(), Source::Process(index)));
+			}
+
+			let configs: Vec<&ModuleRunnerConfig> =
+				declared_modules.iter().map(|(config, _)| config).collect();
+			for (index, err) in check_dependencies(&configs) {
+				errors.push(match &declared_modules[index].1 {
+					Source::Manifest(manifest_file) => err.in_file(manifest_file),
+					Source::Process(process_index) => {
+						let err = err.with_prefix(&format!("processes[{}]", process_index));
+						sources.locate(err).in_file(&file)
+					}
+				});
+			}
+		}
+		Err(err) => errors.push(err.in_file(&file)),
+	}
+
+	if errors.is_empty() {
+		logger::info(&format!(
+			"{} is valid, along with {} module(s)",
+			file, module_count
+		));
+		return;
+	}
+	for err in errors.iter() {
+		logger::error(&format!("{}", err));
+	}
+	logger::error(&format!("Found {} problem(s)", errors.len()));
+	std::process::exit(1);
+}
+
+// Where a module is declared: in a module's manifest, or in the config's processes
+enum Source {
+	Manifest(String),
+	Process(usize),
+}
+
+// Module names need to be unique, since they're used for the log directories
+fn check_name(module_names: &mut Vec<String>, name: &str) -> Option<ConfigError> {
+	if module_names.iter().any(|module_name| module_name == name) {
+		return Some(ConfigError::new(
+			"name",
+			&format!("There's already a module named '{}'", name),
+		));
+	}
+	module_names.push(name.to_string());
+	None
+}
+
+fn check_secrets_store(
+	config: &ModuleRunnerConfig,
+	has_secrets_store: bool,
+) -> Option<ConfigError> {
+	if uses_secrets_store(config) && !has_secrets_store {
+		Some(ConfigError::new(
+			"secrets",
+			"Secrets are read from the secrets store, but the config has no secrets_store",
+		))
+	} else {
+		None
+	}
+}
@@ -0,0 +1,203 @@
+use crate::{
+	cli::get_date_time,
+	constants, logger,
+	models::{GuillotineSpecificConfig, EVENT_HOOKS},
+};
+
+use clap::ArgMatches;
+use futures::{
+	channel::mpsc::{unbounded, UnboundedSender},
+	StreamExt,
+};
+use juno::{
+	models::{Number, Value},
+	JunoModule,
+};
+use std::{collections::HashMap, sync::Mutex};
+
+lazy_static! {
+	static ref EVENT_NOTIFIER: Mutex<Option<UnboundedSender<()>>> = Mutex::new(None);
+}
+
+pub async fn watch_events(config: GuillotineSpecificConfig, args: &ArgMatches<'_>) {
+	let mut module = if config.juno.connection_type == "unix_socket" {
+		let socket_path = config.juno.socket_path.as_ref().unwrap();
+		JunoModule::from_unix_socket(socket_path)
+	} else {
+		let port = config.juno.port.as_ref().unwrap();
+		let bind_addr = config.juno.bind_addr.as_ref().unwrap();
+		JunoModule::from_inet_socket(bind_addr, *port)
+	};
+	let modules: Option<Vec<&str>> = args.values_of("module").map(|modules| modules.collect());
+	let types: Option<Vec<&str>> = args.values_of("type").map(|types| types.collect());
+	let json = args.is_present("json");
+
+	module
+		.initialize(
+			&format!("{}-cli", constants::APP_NAME),
+			constants::APP_VERSION,
+			HashMap::new(),
+		)
+		.await
+		.unwrap();
+
+	let (sender, mut receiver) = unbounded::<()>();
+	*EVENT_NOTIFIER.lock().unwrap() = Some(sender);
+	for hook in EVENT_HOOKS.iter() {
+		module
+			.register_hook(&format!("{}.{}", constants::APP_NAME, hook), on_event)
+			.await
+			.unwrap();
+	}
+
+	// Only the events that happen from here on are printed
+	let mut last_id = match list_events(&mut module, None).await {
+		Some(events) => events.last().and_then(|event| get_number(event, "id")),
+		None => return,
+	};
+
+	// Hooks don't carry any data, so every time one is triggered the new events are fetched
+	while receiver.next().await.is_some() {
+		// Any events that came in together are fetched at once
+		while let Ok(Some(())) = receiver.try_next() {}
+
+		let events = match list_events(&mut module, last_id).await {
+			Some(events) => events,
+			None => return,
+		};
+		for event in events {
+			last_id = get_number(&event, "id").or(last_id);
+
+			let name = get_string(&event, "module").unwrap_or_default();
+			let event_type = get_string(&event, "type").unwrap_or_default();
+			if let Some(modules) = &modules {
+				// Instances match by the name of their module as well. Eg: app:1 by app
+				let module_name = name.split(':').next().unwrap_or_default();
+				if !modules.contains(&name.as_str()) && !modules.contains(&module_name) {
+					continue;
+				}
+			}
+			if let Some(types) = &types {
+				if !types.contains(&event_type.as_str()) {
+					continue;
+				}
+			}
+
+			if json {
+				println!("{}", to_json(&event));
+			} else {
+				print_event(&event, &name, &event_type);
+			}
+		}
+	}
+}
+
+// Stops watching for events
+pub fn on_exit() {
+	EVENT_NOTIFIER.lock().unwrap().take();
+}
+
+fn on_event(_: Value) {
+	if let Some(sender) = EVENT_NOTIFIER.lock().unwrap().as_ref() {
+		sender.unbounded_send(()).ok();
+	}
+}
+
+async fn list_events(module: &mut JunoModule, after: Option<u64>) -> Option<Vec<Value>> {
+	let mut args = HashMap::new();
+	if let Some(after) = after {
+		args.insert(String::from("after"), Value::Number(Number::PosInt(after)));
+	}
+	let events = match module
+		.call_function(&format!("{}.listEvents", constants::APP_NAME), args)
+		.await
+	{
+		Ok(events) => events,
+		Err(err) => {
+			logger::error(&format!("Error listing events: {}", err));
+			return None;
+		}
+	};
+	match events {
+		Value::Array(events) => Some(events),
+		events => {
+			logger::error(&format!("Expected array response. Got {:?}", events));
+			None
+		}
+	}
+}
+
+fn print_event(event: &Value, name: &str, event_type: &str) {
+	let description = match event_type {
+		"processStarted" => String::from("started"),
+		"processRestarted" => String::from("restarted"),
+		"processExited" => match get_string(event, "reason") {
+			Some(reason) if get_bool(event, "crashed") => format!("crashed: {}", reason),
+			Some(reason) => format!("stopped: {}", reason),
+			None => String::from("exited"),
+		},
+		"processErrored" => format!(
+			"couldn't be started: {}",
+			get_string(event, "error").unwrap_or_default()
+		),
+		"processReloaded" => match get_string(event, "error") {
+			Some(error) => format!("couldn't be reloaded: {}", error),
+			None => String::from("reloaded"),
+		},
+		"juno.restarted" => String::from("restarted"),
+		event_type => String::from(event_type),
+	};
+	let timestamp = get_number(event, "timestamp").unwrap_or_default();
+	println!(
+		"[{}] {}: {}",
+		get_date_time(timestamp as i64),
+		name,
+		description
+	);
+}
+
+fn get_string(event: &Value, key: &str) -> Option<String> {
+	event
+		.as_object()
+		.and_then(|event| event.get(key))
+		.and_then(|value| value.as_string())
+		.cloned()
+}
+
+fn get_bool(event: &Value, key: &str) -> bool {
+	event
+		.as_object()
+		.and_then(|event| event.get(key))
+		.and_then(|value| value.as_bool())
+		== Some(&true)
+}
+
+fn get_number(event: &Value, key: &str) -> Option<u64> {
+	match event
+		.as_object()
+		.and_then(|event| event.get(key))
+		.and_then(|value| value.as_number())
+	{
+		Some(Number::PosInt(number)) => Some(*number),
+		Some(Number::NegInt(number)) => Some(*number as u64),
+		Some(Number::Float(number)) => Some(*number as u64),
+		None => None,
+	}
+}
+
+fn to_json(value: &Value) -> serde_json::Value {
+	match value {
+		Value::Null => serde_json::Value::Null,
+		Value::Bool(value) => serde_json::Value::from(*value),
+		Value::Number(Number::PosInt(number)) => serde_json::Value::from(*number),
+		Value::Number(Number::NegInt(number)) => serde_json::Value::from(*number),
+		Value::Number(Number::Float(number)) => serde_json::Value::from(*number),
+		Value::String(value) => serde_json::Value::from(value.as_str()),
+		Value::Array(values) => serde_json::Value::Array(values.iter().map(to_json).collect()),
+		Value::Object(map) => serde_json::Value::Object(
+			map.iter()
+				.map(|(key, value)| (key.clone(), to_json(value)))
+				.collect(),
+		),
+	}
+}
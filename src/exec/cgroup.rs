@@ -0,0 +1,222 @@
+use crate::{
+	logger,
+	models::{ModuleCgroupConfig, ResourceLimit},
+};
+use std::{
+	ffi::{CStr, CString},
+	fs, io,
+	path::{Path, PathBuf},
+	sync::Arc,
+	thread,
+	time::Duration,
+};
+
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+const CPU_PERIOD: u64 = 100_000;
+
+lazy_static! {
+	// The sub-tree the modules' cgroups are created in. It's set up the first time
+	// it's needed, since it moves guillotine itself to another cgroup
+	static ref CGROUP_ROOT: Result<PathBuf, String> = set_up_root();
+}
+
+// A cgroup v2 sub-tree that a module is run in. Clones share the cgroup, which is
+// removed once the last of them is dropped. Each of them keeps track of OOM kills
+// on its own, since they're held by different processes of the module
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+	dir: Arc<CgroupDir>,
+	oom_kills: u64,
+}
+
+#[derive(Debug)]
+struct CgroupDir {
+	path: PathBuf,
+	procs_path: CString,
+}
+
+impl Cgroup {
+	pub fn create(module_name: &str, config: &ModuleCgroupConfig) -> Result<Self, String> {
+		let root = CGROUP_ROOT.as_ref().map_err(String::clone)?;
+
+		let name: String = module_name
+			.chars()
+			.map(|c| {
+				if c.is_ascii_alphanumeric() || c == '-' {
+					c
+				} else {
+					'_'
+				}
+			})
+			.collect();
+		let path = root.join(name);
+		fs::create_dir_all(&path).map_err(|err| to_error(&path, err))?;
+
+		set_limit(
+			&path.join("memory.max"),
+			config.memory_max.map(to_cgroup_value),
+		)?;
+		set_limit(
+			&path.join("cpu.max"),
+			config
+				.cpu_max
+				.map(|cpus| format!("{} {}", (cpus * CPU_PERIOD as f64) as u64, CPU_PERIOD)),
+		)?;
+		set_limit(&path.join("pids.max"), config.pids_max.map(to_cgroup_value))?;
+
+		let procs_path = CString::new(path.join("cgroup.procs").to_string_lossy().as_bytes())
+			.map_err(|err| format!("{}", err))?;
+		let mut cgroup = Cgroup {
+			dir: Arc::new(CgroupDir { path, procs_path }),
+			oom_kills: 0,
+		};
+		cgroup.oom_kills = cgroup.get_oom_kills();
+		Ok(cgroup)
+	}
+
+	pub fn procs_path(&self) -> CString {
+		self.dir.procs_path.clone()
+	}
+
+	// Moves the current process into the cgroup, given its cgroup.procs path.
+	// This is called in the child process between fork and exec, so it sticks
+	// to raw syscalls that don't allocate
+	pub fn join(procs_path: &CStr) -> io::Result<()> {
+		unsafe {
+			let fd = libc::open(procs_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+			if fd < 0 {
+				return Err(io::Error::last_os_error());
+			}
+			let result = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+			libc::close(fd);
+			if result < 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		Ok(())
+	}
+
+	// Checks if the kernel OOM-killed anything in the cgroup since the last check
+	pub fn was_oom_killed(&mut self) -> bool {
+		let oom_kills = self.get_oom_kills();
+		let was_oom_killed = oom_kills > self.oom_kills;
+		self.oom_kills = oom_kills;
+		was_oom_killed
+	}
+
+	fn get_oom_kills(&self) -> u64 {
+		fs::read_to_string(self.dir.path.join("memory.events"))
+			.unwrap_or_default()
+			.lines()
+			.find_map(|line| {
+				let mut parts = line.split_whitespace();
+				if parts.next()? == "oom_kill" {
+					parts.next()?.parse().ok()
+				} else {
+					None
+				}
+			})
+			.unwrap_or(0)
+	}
+}
+
+impl Drop for CgroupDir {
+	// A cgroup can only be removed once it's empty. The processes that were just
+	// killed can take a moment to leave it
+	fn drop(&mut self) {
+		for _ in 0..10 {
+			match fs::remove_dir(&self.path) {
+				Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {
+					thread::sleep(Duration::from_millis(10))
+				}
+				Err(err) if err.kind() != io::ErrorKind::NotFound => {
+					logger::warn(&to_removal_error(&self.path, &err.to_string()));
+					return;
+				}
+				_ => return,
+			}
+		}
+		logger::warn(&to_removal_error(
+			&self.path,
+			"there are still processes in it",
+		));
+	}
+}
+
+// Sets up the sub-tree for the modules' cgroups, if it hasn't been already
+pub fn set_up() -> Result<(), String> {
+	CGROUP_ROOT.as_ref().map(|_| ()).map_err(String::clone)
+}
+
+// Sets up the sub-tree for the modules' cgroups, under guillotine's own cgroup.
+// Only the root of the hierarchy can have both processes and sub-trees with
+// controllers enabled, so elsewhere guillotine moves itself into a leaf first
+fn set_up_root() -> Result<PathBuf, String> {
+	if !Path::new(CGROUP_MOUNT).join("cgroup.controllers").exists() {
+		return Err(format!("cgroup v2 is not mounted at {}", CGROUP_MOUNT));
+	}
+	let cgroups = fs::read_to_string("/proc/self/cgroup")
+		.map_err(|err| to_error("/proc/self/cgroup", err))?;
+	let own_cgroup = cgroups
+		.lines()
+		.find_map(|line| line.strip_prefix("0::"))
+		.ok_or_else(|| String::from("Guillotine isn't in a cgroup v2 hierarchy"))?;
+	let own_path = Path::new(CGROUP_MOUNT).join(own_cgroup.trim_start_matches('/'));
+
+	if own_path != Path::new(CGROUP_MOUNT) {
+		let leaf = own_path.join("supervisor");
+		fs::create_dir_all(&leaf).map_err(|err| to_error(&leaf, err))?;
+		write_value(&leaf.join("cgroup.procs"), "0")?;
+	}
+	enable_controllers(&own_path)?;
+
+	let root = own_path.join("guillotine");
+	fs::create_dir_all(&root).map_err(|err| to_error(&root, err))?;
+	enable_controllers(&root)?;
+	Ok(root)
+}
+
+fn enable_controllers(path: &Path) -> Result<(), String> {
+	let available = fs::read_to_string(path.join("cgroup.controllers"))
+		.map_err(|err| to_error(path.join("cgroup.controllers"), err))?;
+	let controllers = ["memory", "cpu", "pids"]
+		.iter()
+		.filter(|controller| available.split_whitespace().any(|c| c == **controller))
+		.map(|controller| format!("+{}", controller))
+		.collect::<Vec<_>>()
+		.join(" ");
+	write_value(&path.join("cgroup.subtree_control"), &controllers)
+}
+
+// Sets the limit if it's configured. Otherwise resets it (if the controller is
+// available), in case it was set by a previous run with a different config
+fn set_limit(path: &Path, value: Option<String>) -> Result<(), String> {
+	match value {
+		Some(value) => write_value(path, &value),
+		None if path.exists() => write_value(path, "max"),
+		None => Ok(()),
+	}
+}
+
+fn write_value(path: &Path, value: &str) -> Result<(), String> {
+	fs::write(path, value).map_err(|err| to_error(path, err))
+}
+
+fn to_cgroup_value(limit: ResourceLimit) -> String {
+	match limit {
+		ResourceLimit::Limited(value) => value.to_string(),
+		ResourceLimit::Unlimited => String::from("max"),
+	}
+}
+
+fn to_removal_error(path: &Path, reason: &str) -> String {
+	format!("Couldn't remove cgroup at {}: {}", path.display(), reason)
+}
+
+fn to_error<P: AsRef<Path>>(path: P, err: io::Error) -> String {
+	format!(
+		"Error setting up cgroup at {}: {}",
+		path.as_ref().display(),
+		err
+	)
+}
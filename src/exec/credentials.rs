@@ -0,0 +1,172 @@
+use crate::models::ModuleRunnerConfig;
+
+#[cfg(target_family = "unix")]
+use crate::exec::process::to_io_error;
+#[cfg(target_family = "unix")]
+use nix::unistd::{self, Gid, Group, Uid, User};
+#[cfg(target_family = "unix")]
+use std::path::PathBuf;
+
+// The user, group and supplementary groups that a module should be run as
+#[cfg(target_family = "unix")]
+#[derive(Debug, Clone)]
+pub struct Credentials {
+	pub uid: Uid,
+	pub gid: Gid,
+	pub groups: Option<Vec<Gid>>,
+	pub user: Option<User>,
+}
+
+#[cfg(target_family = "unix")]
+impl Credentials {
+	// Switches the current process to these credentials.
+	// This is called in the child process, right before it exec's the module
+	pub fn apply(&self) -> std::io::Result<()> {
+		#[cfg(not(any(target_os = "ios", target_os = "macos")))]
+		{
+			if let Some(groups) = &self.groups {
+				unistd::setgroups(groups).map_err(to_io_error)?;
+			}
+		}
+		unistd::setgid(self.gid).map_err(to_io_error)?;
+		unistd::setuid(self.uid).map_err(to_io_error)?;
+		Ok(())
+	}
+
+	pub fn home_dir(&self) -> Option<PathBuf> {
+		self.user.as_ref().map(|user| user.dir.clone())
+	}
+
+	pub fn user_name(&self) -> Option<String> {
+		self.user.as_ref().map(|user| user.name.clone())
+	}
+}
+
+// Figures out the credentials a module is supposed to run as.
+// Returns None if the module should just run as guillotine does, and an error
+// if the users / groups don't exist or guillotine can't switch to them
+#[cfg(target_family = "unix")]
+pub fn resolve(config: &ModuleRunnerConfig) -> Result<Option<Credentials>, String> {
+	if config.user.is_none() && config.group.is_none() && config.groups.is_none() {
+		return Ok(None);
+	}
+
+	let user = match &config.user {
+		Some(user) => Some(get_user(user)?),
+		None => None,
+	};
+
+	let uid = match &user {
+		Some(user) => user.uid,
+		None => unistd::getuid(),
+	};
+	let gid = match &config.group {
+		Some(group) => get_group(group)?,
+		None => match &user {
+			Some(user) => user.gid,
+			None => unistd::getgid(),
+		},
+	};
+
+	let groups = match &config.groups {
+		Some(groups) => Some(
+			groups
+				.iter()
+				.map(|group| get_group(group))
+				.collect::<Result<Vec<_>, _>>()?,
+		),
+		None => match &user {
+			Some(user) => get_default_groups(user, gid)?,
+			// Otherwise it would keep guillotine's own supplementary groups along with the new group
+			None if gid != unistd::getgid() => Some(vec![gid]),
+			None => None,
+		},
+	};
+
+	#[cfg(any(target_os = "ios", target_os = "macos"))]
+	{
+		if config.groups.is_some() {
+			return Err(String::from(
+				"Supplementary groups are not supported on this platform",
+			));
+		}
+	}
+
+	// Only root can switch to a different user or group
+	if !unistd::geteuid().is_root() {
+		if uid != unistd::geteuid() || gid != unistd::getegid() || config.groups.is_some() {
+			return Err(format!(
+				"Guillotine needs to run as root to run '{}' as a different user or group",
+				config.name
+			));
+		}
+		// Already running as the right user. Nothing to switch
+		return Ok(None);
+	}
+
+	Ok(Some(Credentials {
+		uid,
+		gid,
+		groups,
+		user,
+	}))
+}
+
+#[cfg(target_family = "windows")]
+pub fn resolve(config: &ModuleRunnerConfig) -> Result<Option<()>, String> {
+	if config.user.is_none() && config.group.is_none() && config.groups.is_none() {
+		Ok(None)
+	} else {
+		Err(String::from(
+			"Running modules as a different user or group is not supported on Windows",
+		))
+	}
+}
+
+#[cfg(target_family = "unix")]
+fn get_user(user: &str) -> Result<User, String> {
+	let result = match user.parse::<u32>() {
+		Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+		Err(_) => User::from_name(user),
+	};
+	match result {
+		Ok(Some(user)) => Ok(user),
+		Ok(None) => Err(format!("No user found with the name '{}'", user)),
+		Err(err) => Err(format!("Error looking up user '{}': {}", user, err)),
+	}
+}
+
+#[cfg(target_family = "unix")]
+fn get_group(group: &str) -> Result<Gid, String> {
+	let result = match group.parse::<u32>() {
+		Ok(gid) => Group::from_gid(Gid::from_raw(gid)),
+		Err(_) => Group::from_name(group),
+	};
+	match result {
+		Ok(Some(group)) => Ok(group.gid),
+		Ok(None) => Err(format!("No group found with the name '{}'", group)),
+		Err(err) => Err(format!("Error looking up group '{}': {}", group, err)),
+	}
+}
+
+#[cfg(all(
+	target_family = "unix",
+	not(any(target_os = "ios", target_os = "macos"))
+))]
+fn get_default_groups(user: &User, gid: Gid) -> Result<Option<Vec<Gid>>, String> {
+	use std::ffi::CString;
+
+	let name = CString::new(user.name.as_str()).map_err(|err| format!("{}", err))?;
+	match unistd::getgrouplist(&name, gid) {
+		Ok(groups) => Ok(Some(groups)),
+		Err(err) => Err(format!(
+			"Error looking up the groups of user '{}': {}",
+			user.name, err
+		)),
+	}
+}
+
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+fn get_default_groups(_: &User, _: Gid) -> Result<Option<Vec<Gid>>, String> {
+	Ok(None)
+}
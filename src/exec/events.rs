@@ -0,0 +1,56 @@
+use crate::models::{Event, EventKind};
+
+use futures::channel::mpsc::UnboundedSender;
+
+use std::{
+	collections::VecDeque,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+// How many of the latest events are kept around to be looked up
+const MAX_EVENTS: usize = 1000;
+
+// Keeps the latest events, and passes every new one on to the subscribers
+#[derive(Default)]
+pub struct EventLog {
+	last_id: u64,
+	events: VecDeque<Event>,
+	subscribers: Vec<UnboundedSender<Event>>,
+}
+
+impl EventLog {
+	pub fn add(&mut self, module_id: u64, module: String, kind: EventKind) -> Event {
+		self.last_id += 1;
+		let event = Event {
+			id: self.last_id,
+			timestamp: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.expect("Time went backwards. Wtf?")
+				.as_millis() as u64,
+			module_id,
+			module,
+			kind,
+		};
+		if self.events.len() == MAX_EVENTS {
+			self.events.pop_front();
+		}
+		self.events.push_back(event.clone());
+		// Subscribers that have gone away are dropped
+		self.subscribers
+			.retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+		event
+	}
+
+	pub fn subscribe(&mut self, subscriber: UnboundedSender<Event>) {
+		self.subscribers.push(subscriber);
+	}
+
+	// The events after the one with the given ID, or all of them
+	pub fn list(&self, after: Option<u64>) -> Vec<Event> {
+		self.events
+			.iter()
+			.filter(|event| after.is_none_or(|after| event.id > after))
+			.cloned()
+			.collect()
+	}
+}
@@ -0,0 +1,192 @@
+use crate::{
+	exec::credentials,
+	logger,
+	models::{HookConfig, ModuleRunnerConfig},
+	utils::env,
+};
+
+use async_std::task;
+use std::{
+	fmt,
+	fs::OpenOptions,
+	path::Path,
+	process::{Child, Command, Stdio},
+	time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+	PreStart,
+	PostStart,
+	PreStop,
+	PostStop,
+}
+
+impl Hook {
+	fn get_config(self, config: &ModuleRunnerConfig) -> Option<&HookConfig> {
+		let hooks = config.hooks.as_ref()?;
+		match self {
+			Hook::PreStart => hooks.pre_start.as_ref(),
+			Hook::PostStart => hooks.post_start.as_ref(),
+			Hook::PreStop => hooks.pre_stop.as_ref(),
+			Hook::PostStop => hooks.post_stop.as_ref(),
+		}
+	}
+}
+
+impl fmt::Display for Hook {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Hook::PreStart => write!(f, "pre_start"),
+			Hook::PostStart => write!(f, "post_start"),
+			Hook::PreStop => write!(f, "pre_stop"),
+			Hook::PostStop => write!(f, "post_stop"),
+		}
+	}
+}
+
+// A hook that's been started, and hasn't been waited for yet
+#[derive(Debug)]
+pub struct RunningHook {
+	child: Child,
+	timeout: u64,
+	started_at: Instant,
+}
+
+impl RunningHook {
+	// Checks if the hook has finished, without waiting for it. If it's
+	// taken too long, it's killed, along with everything it spawned
+	pub fn poll(&mut self) -> Option<Result<(), String>> {
+		match self.child.try_wait() {
+			Ok(Some(status)) if status.success() => return Some(Ok(())),
+			Ok(Some(status)) => {
+				return Some(Err(match status.code() {
+					Some(code) => format!("exited with code {}", code),
+					None => String::from("killed by a signal"),
+				}))
+			}
+			Ok(None) => {}
+			Err(err) => return Some(Err(format!("{}", err))),
+		}
+		if self.started_at.elapsed() >= Duration::from_millis(self.timeout) {
+			self.kill();
+			return Some(Err(format!("timed out after {}ms", self.timeout)));
+		}
+		None
+	}
+
+	pub fn kill(&mut self) {
+		#[cfg(target_family = "unix")]
+		{
+			use nix::{
+				sys::signal::{self, Signal},
+				unistd::Pid,
+			};
+			signal::killpg(Pid::from_raw(self.child.id() as i32), Signal::SIGKILL).ok();
+		}
+		#[cfg(target_family = "windows")]
+		self.child.kill().ok();
+		self.child.wait().ok();
+	}
+
+	pub fn pid(&self) -> u32 {
+		self.child.id()
+	}
+}
+
+// Runs one of a module's hooks (if it has it) as the module's user, and waits
+// for it to finish
+pub async fn run(
+	hook: Hook,
+	name: &str,
+	config: &ModuleRunnerConfig,
+	global_envs: &[(String, String)],
+	working_dir: &str,
+	log_dir: &Option<String>,
+) -> Result<(), String> {
+	let mut running_hook = match start(hook, name, config, global_envs, working_dir, log_dir)? {
+		Some(running_hook) => running_hook,
+		None => return Ok(()),
+	};
+	loop {
+		if let Some(result) = running_hook.poll() {
+			return result;
+		}
+		task::sleep(Duration::from_millis(50)).await;
+	}
+}
+
+// Starts one of a module's hooks (if it has it) as the module's user.
+// Its output goes to <hook>.log in the module's log dir
+pub fn start(
+	hook: Hook,
+	name: &str,
+	config: &ModuleRunnerConfig,
+	global_envs: &[(String, String)],
+	working_dir: &str,
+	log_dir: &Option<String>,
+) -> Result<Option<RunningHook>, String> {
+	let hook_config = match hook.get_config(config) {
+		Some(hook_config) => hook_config,
+		None => return Ok(None),
+	};
+	logger::info(&format!("Running the {} hook of '{}'", hook, name));
+
+	let envs = env::get_module_envs(global_envs, config, working_dir)?;
+	#[cfg_attr(target_family = "windows", allow(unused_variables))]
+	let credentials = credentials::resolve(config)?;
+
+	#[cfg(target_family = "unix")]
+	let mut command = {
+		let mut command = Command::new("sh");
+		command.arg("-c").arg(&hook_config.command);
+		command
+	};
+	#[cfg(target_family = "windows")]
+	let mut command = {
+		let mut command = Command::new("cmd");
+		command.arg("/C").arg(&hook_config.command);
+		command
+	};
+	command
+		.current_dir(working_dir)
+		.envs(envs)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::null());
+
+	if let Some(log_dir) = log_dir {
+		let output = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(Path::new(log_dir).join(format!("{}.log", hook)));
+		if let Ok(output) = output {
+			if let Ok(error) = output.try_clone() {
+				command
+					.stdout(Stdio::from(output))
+					.stderr(Stdio::from(error));
+			}
+		}
+	}
+
+	// Run the hook in a process group of its own, so that everything
+	// it spawns can be killed along with it if it times out
+	#[cfg(target_family = "unix")]
+	unsafe {
+		use crate::exec::process::to_io_error;
+		use nix::unistd;
+		use std::os::unix::process::CommandExt;
+
+		command.pre_exec(|| unistd::setsid().map(|_| ()).map_err(to_io_error));
+		if let Some(credentials) = credentials {
+			command.pre_exec(move || credentials.apply());
+		}
+	}
+
+	let child = command.spawn().map_err(|err| format!("{}", err))?;
+	Ok(Some(RunningHook {
+		child,
+		timeout: hook_config.timeout.unwrap_or(30000),
+		started_at: Instant::now(),
+	}))
+}
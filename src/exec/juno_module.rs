@@ -1,65 +1,155 @@
 use crate::{
-	exec::process::ProcessRunner,
-	models::{GuillotineMessage, GuillotineSpecificConfig, ModuleRunningStatus},
-	utils::constants,
+	exec::supervisor::{self, MAX_SUPERVISORS},
+	logger,
+	models::{
+		CrashReason, Event, EventKind, GuillotineMessage, GuillotineSpecificConfig,
+		ModuleRunningStatus, ProcessInfo,
+	},
+	utils::{constants, secrets},
 };
-use std::{collections::HashMap, sync::Mutex};
+use std::{collections::HashMap, thread, time::Duration};
 
-use async_std::task;
+use async_std::{future, task};
 use futures::{
-	channel::{mpsc::UnboundedSender, oneshot::channel},
-	SinkExt,
+	channel::{
+		mpsc::{unbounded, UnboundedSender},
+		oneshot::{channel, Receiver},
+	},
+	SinkExt, StreamExt,
 };
 use juno::{
 	models::{Number, Value},
 	JunoModule,
 };
 
-lazy_static! {
-	static ref MESSAGE_SENDER: Mutex<Option<UnboundedSender<GuillotineMessage>>> = Mutex::new(None);
+// How long Juno gets to acknowledge a hook being triggered
+const HOOK_TIMEOUT: u64 = 1000;
+
+type Handler = fn(HashMap<String, Value>) -> Value;
+
+// The functions declared with Juno, for the supervisor in each slot
+const HANDLERS: [[(&str, Handler); 5]; MAX_SUPERVISORS] = [
+	handlers::<0>(),
+	handlers::<1>(),
+	handlers::<2>(),
+	handlers::<3>(),
+	handlers::<4>(),
+	handlers::<5>(),
+	handlers::<6>(),
+	handlers::<7>(),
+];
+
+const fn handlers<const SLOT: usize>() -> [(&'static str, Handler); 5] {
+	[
+		("listProcesses", list_processes::<SLOT>),
+		("restartProcess", restart_process::<SLOT>),
+		("scaleProcess", scale_process::<SLOT>),
+		("reloadProcess", reload_process::<SLOT>),
+		("listEvents", list_events::<SLOT>),
+	]
+}
+
+// The guillotine module's connection to Juno. It lives on a thread of its own, so that
+// triggering hooks never waits on function calls that are waiting on the supervision loop
+pub struct GuillotineModule {
+	events: UnboundedSender<Event>,
+	closed: Receiver<()>,
+}
+
+impl GuillotineModule {
+	// Triggers the Juno hook for an event. Eg: guillotine.processExited
+	pub fn trigger(&self, event: &Event) {
+		self.events.unbounded_send(event.clone()).ok();
+	}
+
+	pub async fn close(self) {
+		drop(self.events);
+		// If Juno has died, the hooks still waiting to be triggered never will be
+		future::timeout(Duration::from_millis(HOOK_TIMEOUT), self.closed)
+			.await
+			.ok();
+	}
 }
 
+// Connects to Juno for the supervisor in the slot
 pub async fn setup_module(
 	config: GuillotineSpecificConfig,
-	sender: UnboundedSender<GuillotineMessage>,
-) -> JunoModule {
-	let mut message_sender = MESSAGE_SENDER.lock().unwrap();
-	*message_sender = Some(sender);
-	drop(message_sender);
+	slot: usize,
+) -> Result<GuillotineModule, String> {
+	let (events, mut event_receiver) = unbounded::<Event>();
+	let (initialized_sender, initialized) = channel::<Result<(), String>>();
+	let (closed_sender, closed) = channel::<()>();
+	thread::spawn(move || {
+		task::block_on(async move {
+			let mut module = connect(&config);
+			if let Err(err) = declare_functions(&mut module, slot).await {
+				initialized_sender.send(Err(err)).ok();
+				return;
+			}
+			initialized_sender.send(Ok(())).ok();
 
-	let mut module = if config.juno.connection_type == "unix_socket" {
-		let socket_path = config.juno.socket_path.as_ref().unwrap();
-		JunoModule::from_unix_socket(&socket_path)
-	} else {
-		let port = config.juno.port.as_ref().unwrap();
-		let bind_addr = config.juno.bind_addr.as_ref().unwrap();
+			while let Some(event) = event_receiver.next().await {
+				let hook = event.kind.hook_name();
+				let result = future::timeout(
+					Duration::from_millis(HOOK_TIMEOUT),
+					module.trigger_hook(hook),
+				)
+				.await;
+				match result {
+					Ok(Err(err)) => {
+						logger::warn(&format!("Error triggering the {} hook: {}", hook, err))
+					}
+					Err(_) => logger::warn(&format!("Timed out triggering the {} hook", hook)),
+					_ => {}
+				}
+			}
+			module.close().await;
+			closed_sender.send(()).ok();
+		});
+	});
+	initialized
+		.await
+		.unwrap_or_else(|_| Err(String::from("The connection's thread stopped")))
+		.map_err(|err| format!("Could not initialize Guillotine Juno Module: {}", err))?;
 
-		JunoModule::from_inet_socket(&bind_addr, *port)
-	};
+	Ok(GuillotineModule { events, closed })
+}
 
+async fn declare_functions(module: &mut JunoModule, slot: usize) -> Result<(), String> {
 	module
 		.initialize(constants::APP_NAME, constants::APP_VERSION, HashMap::new())
 		.await
-		.expect("Could not initialize Guillotine Juno Module");
+		.map_err(|err| err.to_string())?;
 
-	module
-		.declare_function("listProcesses", list_processes)
-		.await
-		.unwrap();
+	for (name, handler) in HANDLERS[slot].iter() {
+		module
+			.declare_function(name, *handler)
+			.await
+			.map_err(|err| err.to_string())?;
+	}
 
-	module
-		.declare_function("restartProcess", restart_process)
-		.await
-		.unwrap();
+	Ok(())
+}
 
-	module
+fn connect(config: &GuillotineSpecificConfig) -> JunoModule {
+	if config.juno.connection_type == "unix_socket" {
+		let socket_path = config.juno.socket_path.as_ref().unwrap();
+		JunoModule::from_unix_socket(socket_path)
+	} else {
+		let port = config.juno.port.as_ref().unwrap();
+		let bind_addr = config.juno.bind_addr.as_ref().unwrap();
+
+		JunoModule::from_inet_socket(bind_addr, *port)
+	}
 }
 
-fn list_processes(_: HashMap<String, Value>) -> Value {
-	let message_sender = MESSAGE_SENDER.lock().unwrap();
-	let mut message_sender = message_sender.as_ref().unwrap();
+fn list_processes<const SLOT: usize>(_: HashMap<String, Value>) -> Value {
+	let mut message_sender = match supervisor::get_sender(SLOT) {
+		Some(message_sender) => message_sender,
+		None => return get_error_response("The supervisor has stopped"),
+	};
 
-	let (sender, receiver) = channel::<Vec<ProcessRunner>>();
+	let (sender, receiver) = channel::<Vec<ProcessInfo>>();
 
 	task::block_on(message_sender.send(GuillotineMessage::ListProcesses(sender))).unwrap();
 
@@ -74,14 +164,22 @@ fn list_processes(_: HashMap<String, Value>) -> Value {
 					String::from("id"),
 					Value::Number(Number::PosInt(process.module_id)),
 				);
-				map.insert(String::from("name"), Value::String(process.config.name));
+				map.insert(String::from("name"), Value::String(process.name));
 				map.insert(
 					String::from("status"),
 					Value::String(String::from(match process.status {
 						ModuleRunningStatus::Running => "running",
+						// Cron jobs are only offline between runs
+						ModuleRunningStatus::Offline if process.config.cron.is_some() => {
+							"scheduled"
+						}
 						ModuleRunningStatus::Offline => "offline",
+						ModuleRunningStatus::Waiting => "waiting",
+						ModuleRunningStatus::Completed => "completed",
+						ModuleRunningStatus::Failed => "failed",
 					})),
 				);
+				map.insert(String::from("reloading"), Value::Bool(process.reloading));
 				map.insert(
 					String::from("restarts"),
 					Value::Number(Number::NegInt(process.restarts)),
@@ -94,10 +192,46 @@ fn list_processes(_: HashMap<String, Value>) -> Value {
 					String::from("crashes"),
 					Value::Number(Number::PosInt(process.crashes)),
 				);
+				map.insert(
+					String::from("crashReason"),
+					match process.crash_reason {
+						Some(crash_reason) => Value::String(format!("{}", crash_reason)),
+						None => Value::Null,
+					},
+				);
 				map.insert(
 					String::from("createdAt"),
 					Value::Number(Number::PosInt(process.created_at)),
 				);
+				map.insert(
+					String::from("nextRun"),
+					match process.next_run {
+						Some(next_run) => Value::Number(Number::PosInt(next_run)),
+						None => Value::Null,
+					},
+				);
+				map.insert(
+					String::from("lastRun"),
+					match &process.last_run {
+						Some(Ok(())) => Value::String(String::from("succeeded")),
+						Some(Err(crash_reason)) => {
+							Value::String(format!("failed: {}", crash_reason))
+						}
+						None => Value::Null,
+					},
+				);
+				// The env as it's configured, with the secrets' values left out
+				let mut env: HashMap<String, Value> = process
+					.config
+					.env
+					.unwrap_or_default()
+					.into_iter()
+					.map(|(key, value)| (key, Value::String(value)))
+					.collect();
+				for name in process.config.secrets.unwrap_or_default().keys() {
+					env.insert(name.clone(), Value::String(String::from(secrets::REDACTED)));
+				}
+				map.insert(String::from("env"), Value::Object(env));
 
 				Value::Object(map)
 			})
@@ -105,7 +239,7 @@ fn list_processes(_: HashMap<String, Value>) -> Value {
 	)
 }
 
-fn restart_process(args: HashMap<String, Value>) -> Value {
+fn restart_process<const SLOT: usize>(args: HashMap<String, Value>) -> Value {
 	let pid = args.get("processId");
 	if pid.is_none() {
 		return Value::Object({
@@ -136,8 +270,10 @@ fn restart_process(args: HashMap<String, Value>) -> Value {
 		Number::PosInt(num) => *num,
 	};
 
-	let message_sender = MESSAGE_SENDER.lock().unwrap();
-	let mut message_sender = message_sender.as_ref().unwrap();
+	let mut message_sender = match supervisor::get_sender(SLOT) {
+		Some(message_sender) => message_sender,
+		None => return get_error_response("The supervisor has stopped"),
+	};
 
 	let (sender, receiver) = channel::<bool>();
 
@@ -162,3 +298,161 @@ fn restart_process(args: HashMap<String, Value>) -> Value {
 		})
 	}
 }
+
+fn scale_process<const SLOT: usize>(args: HashMap<String, Value>) -> Value {
+	let name = match args.get("name").and_then(|name| name.as_string()) {
+		Some(name) => name.clone(),
+		None => return get_error_response("No name supplied"),
+	};
+	let instances = match args
+		.get("instances")
+		.and_then(|instances| instances.as_number())
+	{
+		Some(Number::PosInt(instances)) => *instances as usize,
+		Some(Number::NegInt(instances)) if *instances >= 0 => *instances as usize,
+		Some(Number::Float(instances)) if *instances >= 0.0 && instances.fract() == 0.0 => {
+			*instances as usize
+		}
+		Some(_) => return get_error_response("Instances supplied is not a positive number"),
+		None => return get_error_response("No instances supplied"),
+	};
+
+	let mut message_sender = match supervisor::get_sender(SLOT) {
+		Some(message_sender) => message_sender,
+		None => return get_error_response("The supervisor has stopped"),
+	};
+
+	let (sender, receiver) = channel::<Result<(), String>>();
+
+	task::block_on(message_sender.send(GuillotineMessage::ScaleProcess(name, instances, sender)))
+		.unwrap();
+
+	match task::block_on(receiver).unwrap() {
+		Ok(()) => Value::Object({
+			let mut map = HashMap::new();
+			map.insert(String::from("success"), Value::Bool(true));
+			map
+		}),
+		Err(err) => get_error_response(&err),
+	}
+}
+
+fn reload_process<const SLOT: usize>(args: HashMap<String, Value>) -> Value {
+	let name = match args.get("name").and_then(|name| name.as_string()) {
+		Some(name) => name.clone(),
+		None => return get_error_response("No name supplied"),
+	};
+
+	let mut message_sender = match supervisor::get_sender(SLOT) {
+		Some(message_sender) => message_sender,
+		None => return get_error_response("The supervisor has stopped"),
+	};
+
+	let (sender, receiver) = channel::<Result<usize, String>>();
+
+	task::block_on(message_sender.send(GuillotineMessage::ReloadProcess(name, sender))).unwrap();
+
+	match task::block_on(receiver).unwrap() {
+		Ok(instances) => Value::Object({
+			let mut map = HashMap::new();
+			map.insert(String::from("success"), Value::Bool(true));
+			map.insert(
+				String::from("instances"),
+				Value::Number(Number::PosInt(instances as u64)),
+			);
+			map
+		}),
+		Err(err) => get_error_response(&err),
+	}
+}
+
+// Lists the latest events, after the one with the given ID if there is one. Juno hooks
+// can't carry any data, so this is how modules find out what triggered them
+fn list_events<const SLOT: usize>(args: HashMap<String, Value>) -> Value {
+	let after = match args.get("after").and_then(|after| after.as_number()) {
+		Some(Number::PosInt(after)) => Some(*after),
+		Some(Number::NegInt(after)) => Some(*after as u64),
+		Some(Number::Float(after)) => Some(*after as u64),
+		None => None,
+	};
+
+	let mut message_sender = match supervisor::get_sender(SLOT) {
+		Some(message_sender) => message_sender,
+		None => return get_error_response("The supervisor has stopped"),
+	};
+
+	let (sender, receiver) = channel::<Vec<Event>>();
+
+	task::block_on(message_sender.send(GuillotineMessage::ListEvents(after, sender))).unwrap();
+
+	Value::Array(
+		task::block_on(receiver)
+			.unwrap()
+			.into_iter()
+			.map(get_event_value)
+			.collect(),
+	)
+}
+
+fn get_event_value(event: Event) -> Value {
+	let mut map = HashMap::new();
+	map.insert(String::from("id"), Value::Number(Number::PosInt(event.id)));
+	map.insert(
+		String::from("timestamp"),
+		Value::Number(Number::PosInt(event.timestamp)),
+	);
+	map.insert(
+		String::from("moduleId"),
+		Value::Number(Number::PosInt(event.module_id)),
+	);
+	map.insert(String::from("module"), Value::String(event.module));
+	map.insert(
+		String::from("type"),
+		Value::String(String::from(event.kind.hook_name())),
+	);
+	match event.kind {
+		EventKind::Exited { result, crashed } => {
+			map.insert(String::from("crashed"), Value::Bool(crashed));
+			let (code, signal) = match &result {
+				Ok(()) => (Some(0), None),
+				Err(CrashReason::ExitCode(code)) => (Some(*code), None),
+				Err(CrashReason::Signal(signal)) => (None, Some(*signal)),
+				Err(_) => (None, None),
+			};
+			map.insert(
+				String::from("exitCode"),
+				match code {
+					Some(code) => Value::Number(Number::NegInt(code as i64)),
+					None => Value::Null,
+				},
+			);
+			map.insert(
+				String::from("signal"),
+				match signal {
+					Some(signal) => Value::Number(Number::NegInt(signal as i64)),
+					None => Value::Null,
+				},
+			);
+			if let Err(crash_reason) = result {
+				map.insert(
+					String::from("reason"),
+					Value::String(format!("{}", crash_reason)),
+				);
+			}
+		}
+		EventKind::Errored(error) | EventKind::Reloaded(Err(error)) => {
+			map.insert(String::from("error"), Value::String(error));
+		}
+		_ => {}
+	}
+	Value::Object(map)
+}
+
+fn get_error_response(error: &str) -> Value {
+	Value::Object({
+		let mut map = HashMap::new();
+		map.insert(String::from("success"), Value::Bool(false));
+		map.insert(String::from("error"), Value::String(error.to_string()));
+		map
+	})
+}
@@ -0,0 +1,43 @@
+use crate::models::{ModuleLimitsConfig, ResourceLimit};
+use std::io;
+
+// Returns a function that applies the rlimits in the config to the current
+// process. The limits are computed beforehand so that nothing needs to be
+// allocated when it's called between fork and exec
+pub fn get_rlimit_applier(
+	config: &ModuleLimitsConfig,
+) -> impl FnMut() -> io::Result<()> + Send + Sync + 'static {
+	let mut limits = vec![];
+	if let Some(limit) = config.open_files {
+		limits.push((libc::RLIMIT_NOFILE, to_rlimit(limit)));
+	}
+	if let Some(limit) = config.core_size {
+		limits.push((libc::RLIMIT_CORE, to_rlimit(limit)));
+	}
+	if let Some(limit) = config.address_space {
+		limits.push((libc::RLIMIT_AS, to_rlimit(limit)));
+	}
+	if let Some(limit) = config.processes {
+		limits.push((libc::RLIMIT_NPROC, to_rlimit(limit)));
+	}
+
+	move || {
+		for (resource, limit) in limits.iter() {
+			if unsafe { libc::setrlimit(*resource, limit) } != 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		Ok(())
+	}
+}
+
+fn to_rlimit(limit: ResourceLimit) -> libc::rlimit {
+	let value = match limit {
+		ResourceLimit::Limited(value) => value as libc::rlim_t,
+		ResourceLimit::Unlimited => libc::RLIM_INFINITY,
+	};
+	libc::rlimit {
+		rlim_cur: value,
+		rlim_max: value,
+	}
+}
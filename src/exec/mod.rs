@@ -1,3 +1,20 @@
+pub mod credentials;
+pub mod events;
+pub mod hooks;
 pub mod juno_module;
+#[cfg(target_family = "unix")]
+pub mod limits;
+pub mod notifications;
 pub mod process;
 pub mod runner;
+pub mod sandbox;
+pub mod supervisor;
+
+#[cfg(target_os = "linux")]
+pub mod cgroup;
+#[cfg(target_os = "linux")]
+pub mod seccomp;
+#[cfg(target_os = "linux")]
+pub mod subreaper;
+#[cfg(target_os = "linux")]
+pub mod watcher;
@@ -0,0 +1,407 @@
+use crate::{
+	logger,
+	models::{
+		parser, CrashReason, Event, EventKind, NotificationEvent, NotificationTarget,
+		NotificationsConfig,
+	},
+};
+
+use serde_json::{Map, Value};
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	io::{Read, Write},
+	process::{Child, Command, Stdio},
+	sync::{Arc, Mutex, MutexGuard},
+	thread::{self, JoinHandle},
+	time::{Duration, Instant},
+};
+
+// Sends notifications about what happens to the modules to the targets in the config
+pub struct Notifier {
+	targets: Vec<Target>,
+	crash_loop_crashes: usize,
+	crash_loop_period: u64,
+	// When each module crashed, within the crash loop period
+	crashes: HashMap<String, VecDeque<u64>>,
+	// Modules that have been notified about being in a crash loop, till they get out of it
+	crash_looping: HashSet<String>,
+	// The notifications that are still being sent, and the processes sending them
+	sending: Vec<JoinHandle<()>>,
+	pids: Arc<Mutex<HashSet<u32>>>,
+}
+
+struct Target {
+	config: NotificationTarget,
+	// When the notifications of each kind about each module were sent, within the rate limit's period
+	sent: HashMap<(String, NotificationEvent), VecDeque<u64>>,
+	// How many notifications weren't sent because of the rate limit, since the last one that was
+	suppressed: HashMap<(String, NotificationEvent), usize>,
+}
+
+impl Notifier {
+	pub fn new(config: Option<NotificationsConfig>) -> Self {
+		let (targets, crash_loop) = match config {
+			Some(config) => (config.targets, config.crash_loop.unwrap_or_default()),
+			None => (vec![], Default::default()),
+		};
+		Notifier {
+			targets: targets
+				.into_iter()
+				.map(|config| Target {
+					config,
+					sent: HashMap::new(),
+					suppressed: HashMap::new(),
+				})
+				.collect(),
+			crash_loop_crashes: crash_loop.crashes.unwrap_or(5),
+			crash_loop_period: crash_loop.period.unwrap_or(60000),
+			crashes: HashMap::new(),
+			crash_looping: HashSet::new(),
+			sending: vec![],
+			pids: Arc::new(Mutex::new(HashSet::new())),
+		}
+	}
+
+	pub fn notify(&mut self, event: &Event) {
+		if self.targets.is_empty() {
+			return;
+		}
+
+		let (kind, failed) = match &event.kind {
+			EventKind::Started => (NotificationEvent::Started, false),
+			EventKind::Restarted => (NotificationEvent::Restarted, false),
+			EventKind::Exited { crashed: true, .. } => (NotificationEvent::Crashed, true),
+			EventKind::Exited { .. } => (NotificationEvent::Exited, false),
+			EventKind::Errored(_) => (NotificationEvent::Errored, true),
+			EventKind::Reloaded(_) => (NotificationEvent::Reloaded, false),
+			EventKind::JunoRestarted => (NotificationEvent::JunoRestarted, false),
+		};
+		let mut notifications = vec![(kind, get_payload(kind, event))];
+		// Modules that can't be started count towards a crash loop as well
+		if failed && self.is_crash_loop(event) {
+			let mut payload = get_payload(NotificationEvent::CrashLoop, event);
+			payload.insert(
+				String::from("crashes"),
+				Value::from(self.crash_loop_crashes),
+			);
+			payload.insert(String::from("period"), Value::from(self.crash_loop_period));
+			notifications.push((NotificationEvent::CrashLoop, payload));
+		}
+
+		self.sending.retain(|thread| !thread.is_finished());
+		for (kind, payload) in notifications {
+			for target in self.targets.iter_mut() {
+				let mut payload = payload.clone();
+				if target.should_send(kind, event, &mut payload) {
+					self.sending.push(send(
+						target.config.clone(),
+						kind,
+						event.module.clone(),
+						payload,
+						self.pids.clone(),
+					));
+				}
+			}
+		}
+	}
+
+	// The processes that are sending notifications, which the subreaper has to leave alone.
+	// None are started or waited on while it's held, so it has to be held till it's done
+	#[cfg(target_os = "linux")]
+	pub fn lock_pids(&self) -> MutexGuard<'_, HashSet<u32>> {
+		self.pids.lock().unwrap()
+	}
+
+	// Waits for the notifications that are still being sent. Each of them gives up after its timeout
+	pub fn wait(&mut self) {
+		for thread in self.sending.drain(..) {
+			thread.join().ok();
+		}
+	}
+
+	// Whether the module has just crashed often enough to be in a crash loop
+	fn is_crash_loop(&mut self, event: &Event) -> bool {
+		let period = self.crash_loop_period;
+		let crashes = self.crashes.entry(event.module.clone()).or_default();
+		crashes.push_back(event.timestamp);
+		while crashes
+			.front()
+			.is_some_and(|crashed_at| crashed_at + period < event.timestamp)
+		{
+			crashes.pop_front();
+		}
+
+		if crashes.len() < self.crash_loop_crashes {
+			self.crash_looping.remove(&event.module);
+			return false;
+		}
+		self.crash_looping.insert(event.module.clone())
+	}
+}
+
+impl Target {
+	// Whether the notification should be sent to this target, given its filters and rate limit
+	fn should_send(
+		&mut self,
+		kind: NotificationEvent,
+		event: &Event,
+		payload: &mut Map<String, Value>,
+	) -> bool {
+		let events = self
+			.config
+			.events
+			.as_deref()
+			.unwrap_or(&[NotificationEvent::Crashed, NotificationEvent::CrashLoop]);
+		if !events.contains(&kind) {
+			return false;
+		}
+		if let Some(modules) = &self.config.modules {
+			// Instances match by the name of their module as well. Eg: app:1 by app
+			let module_name = event.module.split(':').next().unwrap_or_default();
+			if !modules.contains(&event.module) && !modules.iter().any(|name| name == module_name) {
+				return false;
+			}
+		}
+
+		let rate_limit = self.config.rate_limit.clone().unwrap_or_default();
+		let count = rate_limit.count.unwrap_or(5);
+		let period = rate_limit.period.unwrap_or(300_000);
+		let key = (event.module.clone(), kind);
+		let sent = self.sent.entry(key.clone()).or_default();
+		while sent
+			.front()
+			.is_some_and(|sent_at| sent_at + period < event.timestamp)
+		{
+			sent.pop_front();
+		}
+		if sent.len() >= count {
+			*self.suppressed.entry(key).or_default() += 1;
+			return false;
+		}
+		sent.push_back(event.timestamp);
+		if let Some(suppressed) = self.suppressed.remove(&key) {
+			payload.insert(String::from("suppressed"), Value::from(suppressed));
+		}
+
+		true
+	}
+}
+
+fn send(
+	config: NotificationTarget,
+	kind: NotificationEvent,
+	module: String,
+	payload: Map<String, Value>,
+	pids: Arc<Mutex<HashSet<u32>>>,
+) -> JoinHandle<()> {
+	// Sending can take a while, and shouldn't hold up the supervision loop
+	thread::spawn(move || {
+		let timeout = config.timeout.unwrap_or(10000);
+		let result = match (&config.webhook, &config.command) {
+			(Some(webhook), _) => send_webhook(webhook, &config.headers, &payload, timeout, &pids),
+			(None, Some(command)) => run_command(command, &payload, timeout, &pids),
+			(None, None) => Ok(()),
+		};
+		if let Err(err) = result {
+			logger::warn(&format!(
+				"Couldn't send the {} notification about '{}': {}",
+				kind, module, err
+			));
+		}
+	})
+}
+
+fn get_payload(kind: NotificationEvent, event: &Event) -> Map<String, Value> {
+	let mut payload = Map::new();
+	payload.insert(String::from("event"), Value::from(kind.to_string()));
+	payload.insert(String::from("module"), Value::from(event.module.as_str()));
+	payload.insert(String::from("module_id"), Value::from(event.module_id));
+	payload.insert(String::from("timestamp"), Value::from(event.timestamp));
+	if let Some(hostname) = parser::get_hostname() {
+		payload.insert(String::from("hostname"), Value::from(hostname));
+	}
+	match &event.kind {
+		EventKind::Exited { result, .. } => {
+			let (code, signal) = match result {
+				Ok(()) => (Some(0), None),
+				Err(CrashReason::ExitCode(code)) => (Some(*code), None),
+				Err(CrashReason::Signal(signal)) => (None, Some(*signal)),
+				Err(_) => (None, None),
+			};
+			payload.insert(
+				String::from("exit_code"),
+				code.map_or(Value::Null, Value::from),
+			);
+			payload.insert(
+				String::from("signal"),
+				signal.map_or(Value::Null, Value::from),
+			);
+			if let Err(crash_reason) = result {
+				payload.insert(
+					String::from("reason"),
+					Value::from(crash_reason.to_string()),
+				);
+			}
+		}
+		EventKind::Errored(error) | EventKind::Reloaded(Err(error)) => {
+			payload.insert(String::from("error"), Value::from(error.as_str()));
+		}
+		_ => {}
+	}
+	payload
+}
+
+// POSTs the payload with curl. Everything is passed to it through stdin as a
+// config file, so that tokens in the URL or headers don't show up in ps
+fn send_webhook(
+	url: &str,
+	headers: &Option<HashMap<String, String>>,
+	payload: &Map<String, Value>,
+	timeout: u64,
+	pids: &Mutex<HashSet<u32>>,
+) -> Result<(), String> {
+	let mut config = vec![
+		format!("url = {}", quote(url)),
+		String::from("header = \"Content-Type: application/json\""),
+		format!(
+			"data-binary = {}",
+			quote(&Value::Object(payload.clone()).to_string())
+		),
+	];
+	for (name, value) in headers.iter().flatten() {
+		config.push(format!(
+			"header = {}",
+			quote(&format!("{}: {}", name, value))
+		));
+	}
+
+	let mut command = Command::new("curl");
+	command
+		.args(["--silent", "--show-error", "--fail", "--config", "-"])
+		.arg("--max-time")
+		.arg(format!("{:.3}", timeout as f64 / 1000.0))
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.stderr(Stdio::piped());
+	let mut child =
+		spawn(&mut command, pids).map_err(|err| format!("Couldn't run curl: {}", err))?;
+	// Closing stdin once the config is written lets curl go ahead
+	let written = child
+		.stdin
+		.take()
+		.unwrap()
+		.write_all(config.join("\n").as_bytes());
+	let mut stderr = String::new();
+	child
+		.stderr
+		.take()
+		.unwrap()
+		.read_to_string(&mut stderr)
+		.ok();
+	let status = child.wait();
+	pids.lock().unwrap().remove(&child.id());
+
+	written.map_err(|err| err.to_string())?;
+	if !status.map_err(|err| err.to_string())?.success() {
+		return Err(stderr.trim().to_string());
+	}
+	Ok(())
+}
+
+// Quotes a value for a curl config file
+fn quote(value: &str) -> String {
+	format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Runs the command with the payload in environment variables. Eg: GUILLOTINE_EVENT=crashed,
+// GUILLOTINE_EVENT_MODULE=app, and all of it as JSON in GUILLOTINE_EVENT_JSON
+fn run_command(
+	command: &str,
+	payload: &Map<String, Value>,
+	timeout: u64,
+	pids: &Mutex<HashSet<u32>>,
+) -> Result<(), String> {
+	let mut envs = vec![(
+		String::from("GUILLOTINE_EVENT_JSON"),
+		Value::Object(payload.clone()).to_string(),
+	)];
+	for (key, value) in payload {
+		let name = match key.as_str() {
+			"event" => String::from("GUILLOTINE_EVENT"),
+			key => format!("GUILLOTINE_EVENT_{}", key.to_uppercase()),
+		};
+		match value {
+			Value::Null => {}
+			Value::String(value) => envs.push((name, value.clone())),
+			value => envs.push((name, value.to_string())),
+		}
+	}
+
+	#[cfg(target_family = "unix")]
+	let mut command = {
+		let mut process = Command::new("sh");
+		process.arg("-c").arg(command);
+		process
+	};
+	#[cfg(target_family = "windows")]
+	let mut command = {
+		let mut process = Command::new("cmd");
+		process.arg("/C").arg(command);
+		process
+	};
+	command
+		.envs(envs)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.stderr(Stdio::null());
+	let mut child = spawn(&mut command, pids).map_err(|err| err.to_string())?;
+	let result = wait_for_command(&mut child, timeout);
+	pids.lock().unwrap().remove(&child.id());
+	result
+}
+
+fn wait_for_command(child: &mut Child, timeout: u64) -> Result<(), String> {
+	let started_at = Instant::now();
+	loop {
+		match child.try_wait() {
+			Ok(Some(status)) if status.success() => return Ok(()),
+			Ok(Some(status)) => return Err(format!("The command failed with {}", status)),
+			Ok(None) => {}
+			Err(err) => return Err(err.to_string()),
+		}
+		if started_at.elapsed() >= Duration::from_millis(timeout) {
+			#[cfg(target_family = "unix")]
+			{
+				use nix::{
+					sys::signal::{self, Signal},
+					unistd::Pid,
+				};
+				signal::killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL).ok();
+			}
+			#[cfg(target_family = "windows")]
+			child.kill().ok();
+			child.wait().ok();
+			return Err(format!("The command timed out after {}ms", timeout));
+		}
+		thread::sleep(Duration::from_millis(50));
+	}
+}
+
+// Spawns a process that the subreaper leaves alone, till it's been waited on. The pids are
+// locked while spawning, so that it can't be reaped before it's been added to them
+fn spawn(command: &mut Command, pids: &Mutex<HashSet<u32>>) -> std::io::Result<Child> {
+	// In a process group of its own, so that a Ctrl+C meant for guillotine doesn't cut it
+	// short, and everything it spawns can be killed along with it if it times out
+	#[cfg(target_family = "unix")]
+	unsafe {
+		use crate::exec::process::to_io_error;
+		use nix::unistd;
+		use std::os::unix::process::CommandExt;
+
+		command.pre_exec(|| unistd::setsid().map(|_| ()).map_err(to_io_error));
+	}
+	let mut pids = pids.lock().unwrap();
+	let child = command.spawn()?;
+	pids.insert(child.id());
+	Ok(child)
+}
@@ -1,28 +1,104 @@
+#[cfg(target_family = "unix")]
+use crate::exec::limits;
+#[cfg(target_os = "linux")]
+use crate::exec::{cgroup::Cgroup, sandbox::Sandbox, watcher::Watcher};
 use crate::{
+	exec::{
+		credentials,
+		hooks::{self, Hook, RunningHook},
+	},
 	logger,
-	models::{ModuleRunnerConfig, ModuleRunningStatus},
+	models::{
+		CrashReason, EventKind, ModuleRunnerConfig, ModuleRunningStatus, ModuleType, OverlapPolicy,
+		ProcessInfo, SecretsStoreConfig, WatchConfig,
+	},
+	utils::{cron::Schedule, env, secrets},
+};
+#[cfg(target_os = "linux")]
+use crate::{
+	models::{MemoryRestartConfig, ResourceLimit},
+	utils::procfs,
 };
 use async_std::task;
+use chrono::Local;
+#[cfg(target_family = "unix")]
+use nix::unistd;
 use std::{
+	collections::VecDeque,
 	fs::OpenOptions,
 	path::Path,
-	process::{Child, Command, Stdio},
+	process::{Child, Command, ExitStatus, Stdio},
 	time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Debug)]
 pub struct ProcessRunner {
 	process: Option<Child>,
+	#[cfg(target_family = "unix")]
+	process_group: Option<i32>,
+	#[cfg(target_os = "linux")]
+	cgroup: Option<Cgroup>,
+	#[cfg(target_os = "linux")]
+	watcher: Option<Watcher>,
 	pub log_dir: Option<String>,
 	pub working_dir: String,
 	pub module_id: u64,
+	// Which of the module's instances this is, if it's run in cluster mode
+	pub instance: Option<usize>,
 	pub config: ModuleRunnerConfig,
 	pub status: ModuleRunningStatus,
 	pub restarts: i64,
 	pub uptime: u64,
 	pub last_started_at: u64,
 	pub crashes: u64,
+	pub crash_reason: Option<CrashReason>,
 	pub created_at: u64,
+	// Environment variables from guillotine's config, which the module's own override
+	pub global_envs: Vec<(String, String)>,
+	pub secrets_store: Option<SecretsStoreConfig>,
+	memory_exceeded_since: Option<u64>,
+	last_memory_check: u64,
+	// Set when guillotine asks the process to quit, so that it isn't counted as a crash
+	stopping: bool,
+	// Set when the module has to be reloaded, but its reload hasn't started yet
+	pub reload_queued: bool,
+	reload: Option<Box<Reload>>,
+	// Set while a module it depends on has failed, so that it isn't started
+	blocked: bool,
+	// When a cron job is run next, or when a module with a restart_cron is restarted next
+	pub next_run: Option<u64>,
+	// How a cron job's last run ended
+	pub last_run: Option<Result<(), CrashReason>>,
+	schedule: Option<Schedule>,
+	// Set when a cron job is due, but has to wait for its previous run to finish
+	run_queued: bool,
+	// What has happened to the process since the events were last taken
+	events: Vec<EventKind>,
+	// What's left to do to start or stop the process. The supervision loop carries
+	// on with it every time it checks the module, so that hooks don't hold it up
+	steps: VecDeque<Step>,
+	hook: Option<RunningHook>,
+	quit_sent_at: Option<u64>,
+	// How many times in a row the pre_start hook has failed, and when to try again
+	start_failures: u32,
+	retry_at: u64,
+	// Processes that were replaced, which still have to be stopped
+	retired: Vec<ProcessRunner>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+	Hook(Hook),
+	// Asks the process to quit, and kills it if it doesn't within a second
+	Quit,
+	Start,
+}
+
+// A replacement that's been started while the previous process is still running
+#[derive(Debug)]
+struct Reload {
+	previous: ProcessRunner,
+	started_at: u64,
 }
 
 impl ProcessRunner {
@@ -34,16 +110,43 @@ impl ProcessRunner {
 	) -> Self {
 		ProcessRunner {
 			process: None,
+			#[cfg(target_family = "unix")]
+			process_group: None,
+			#[cfg(target_os = "linux")]
+			cgroup: None,
+			#[cfg(target_os = "linux")]
+			watcher: None,
 			log_dir,
 			working_dir,
 			module_id,
+			instance: None,
 			config,
 			status: ModuleRunningStatus::Offline,
 			restarts: -1,
 			uptime: 0,
 			last_started_at: 0,
 			crashes: 0,
+			crash_reason: None,
 			created_at: get_current_time(),
+			global_envs: vec![],
+			secrets_store: None,
+			memory_exceeded_since: None,
+			last_memory_check: 0,
+			stopping: false,
+			reload_queued: false,
+			reload: None,
+			blocked: false,
+			next_run: None,
+			last_run: None,
+			schedule: None,
+			run_queued: false,
+			events: vec![],
+			steps: VecDeque::new(),
+			hook: None,
+			quit_sent_at: None,
+			start_failures: 0,
+			retry_at: 0,
+			retired: vec![],
 		}
 	}
 
@@ -55,11 +158,63 @@ impl ProcessRunner {
 		let process = self.process.as_mut().unwrap();
 		match process.try_wait() {
 			Ok(Some(status)) => {
-				if !status.success() {
-					self.crashes += 1;
-					self.uptime = 0;
+				// Anything the process left behind in its group goes along with it. Once the
+				// group is empty, its id can be reused, so it's never signalled again
+				#[cfg(target_family = "unix")]
+				{
+					self.kill();
+					self.process_group = None;
+				}
+				let result = if status.success() {
+					Ok(())
+				} else {
+					Err(self.get_crash_reason(status))
+				};
+				if let Err(crash_reason) = &result {
+					if !self.stopping {
+						if self.is_oneshot() {
+							logger::error(&format!(
+								"Process '{}' failed: {}. Modules that depend on it won't be started",
+								self.name(),
+								crash_reason
+							));
+						} else {
+							logger::warn(&format!(
+								"Process '{}' crashed: {}",
+								self.name(),
+								crash_reason
+							));
+						}
+						self.crashes += 1;
+						self.uptime = 0;
+						self.crash_reason = Some(crash_reason.clone());
+					}
+				}
+				// The exit status has been collected. Don't count it again
+				self.process = None;
+				self.status = match &result {
+					_ if !self.is_oneshot() => ModuleRunningStatus::Offline,
+					Ok(()) => {
+						logger::info(&format!("Process '{}' completed", self.name()));
+						ModuleRunningStatus::Completed
+					}
+					Err(_) => ModuleRunningStatus::Failed,
+				};
+				// Oneshot modules are done once they've exited, so their cgroup is removed.
+				// It's set up again if they're restarted
+				#[cfg(target_os = "linux")]
+				{
+					if self.is_oneshot() {
+						self.cgroup = None;
+					}
+				}
+				self.events.push(EventKind::Exited {
+					result: result.clone(),
+					crashed: result.is_err() && !self.stopping,
+				});
+				if self.is_cron_job() {
+					self.last_run = Some(result);
 				}
-				self.status = ModuleRunningStatus::Offline;
 				false
 			} // Process has already exited
 			Ok(None) => {
@@ -68,6 +223,11 @@ impl ProcessRunner {
 				true
 			}
 			Err(_) => {
+				// It's been reaped elsewhere, so its group's id could already have been reused
+				#[cfg(target_family = "unix")]
+				{
+					self.process_group = None;
+				}
 				self.status = ModuleRunningStatus::Offline;
 				self.uptime = 0;
 				false
@@ -75,114 +235,775 @@ impl ProcessRunner {
 		}
 	}
 
-	pub async fn respawn(&mut self) {
-		logger::info(&format!("Respawning '{}'", self.config.name));
-		if self.process.is_some() && self.is_process_running() {
-			self.send_quit_signal();
-			let quit_time = get_current_time();
-			loop {
-				// Give the process some time to die.
-				task::sleep(Duration::from_millis(100)).await;
-				// If the process is not running, then break
-				if !self.is_process_running() {
-					break;
-				}
-				// If the processes is running, check if it's been given enough time.
-				if get_current_time() > quit_time + 1000 {
-					// It's been trying to quit for more than 1 second. Kill it and quit
-					logger::info(&format!("Killing process: {}", self.config.name));
-					self.process.as_mut().unwrap().kill().unwrap_or(());
-					break;
+	// Checks if the process (and its descendants) have been using more memory
+	// than the max_memory_restart threshold for longer than allowed
+	#[cfg(target_os = "linux")]
+	pub fn has_exceeded_memory_threshold(&mut self) -> bool {
+		let (threshold, duration) = match &self.config.max_memory_restart {
+			Some(MemoryRestartConfig {
+				threshold: ResourceLimit::Limited(threshold),
+				duration,
+			}) => (*threshold, duration.unwrap_or(0)),
+			_ => return false,
+		};
+		let pid = match self.pid() {
+			Some(pid) => pid,
+			None => return false,
+		};
+
+		// Walking through /proc isn't cheap. Don't do it more than once a second
+		let now = get_current_time();
+		if now < self.last_memory_check + 1000 {
+			return false;
+		}
+		self.last_memory_check = now;
+
+		let memory = procfs::get_tree_rss(pid as i32);
+		if memory <= threshold {
+			self.memory_exceeded_since = None;
+			return false;
+		}
+		let exceeded_since = *self.memory_exceeded_since.get_or_insert(now);
+		if now - exceeded_since < duration {
+			return false;
+		}
+
+		logger::warn(&format!(
+			"Process '{}' is using {}MB of memory, over its limit of {}MB for {}ms. Restarting it",
+			self.name(),
+			memory / (1 << 20),
+			threshold / (1 << 20),
+			now - exceeded_since
+		));
+		true
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	pub fn has_exceeded_memory_threshold(&mut self) -> bool {
+		false
+	}
+
+	#[cfg(target_os = "linux")]
+	pub fn watch(&mut self, config: &WatchConfig) {
+		match Watcher::new(config, &self.working_dir, &self.log_dir) {
+			Ok(watcher) => self.watcher = Some(watcher),
+			Err(err) => logger::error(&format!(
+				"Not watching '{}' for changes: {}",
+				self.name(),
+				err
+			)),
+		}
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	pub fn watch(&mut self, _: &WatchConfig) {
+		logger::warn(&format!(
+			"Watching for changes is only supported on Linux. Not watching '{}'",
+			self.name()
+		));
+	}
+
+	// Checks if the module's files have changed since it was last checked
+	#[cfg(target_os = "linux")]
+	pub fn has_changed_files(&mut self) -> bool {
+		let changed = self.watcher.as_mut().is_some_and(|watcher| watcher.poll());
+		if changed {
+			logger::info(&format!(
+				"Files of '{}' have changed. Restarting it",
+				self.name()
+			));
+		}
+		changed
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	pub fn has_changed_files(&mut self) -> bool {
+		false
+	}
+
+	// The module's name, along with the instance for modules run in cluster mode. Eg: worker:2
+	pub fn name(&self) -> String {
+		match self.instance {
+			Some(instance) => format!("{}:{}", self.config.name, instance),
+			None => self.config.name.clone(),
+		}
+	}
+
+	// Starts stopping the process, with its pre_stop and post_stop hooks. It
+	// isn't started if it was being started
+	pub fn stop(&mut self) {
+		self.cancel_start();
+		if self.is_busy() || !self.is_process_running() {
+			return;
+		}
+		self.steps.extend(&[
+			Step::Hook(Hook::PreStop),
+			Step::Quit,
+			Step::Hook(Hook::PostStop),
+		]);
+	}
+
+	// Starts restarting the process, unless it's already being started or stopped
+	pub fn respawn(&mut self) {
+		if self.is_busy() {
+			return;
+		}
+		logger::info(&format!("Respawning '{}'", self.name()));
+		self.stop();
+		self.spawn();
+	}
+
+	// Whether the process is being started or stopped
+	pub fn is_busy(&self) -> bool {
+		!self.steps.is_empty()
+	}
+
+	// Whether the module has to wait before it's started again, after its pre_start hook failed
+	pub fn is_backing_off(&self) -> bool {
+		get_current_time() < self.retry_at
+	}
+
+	// Carries on starting or stopping the process, as far as it can without waiting
+	pub fn advance(&mut self) {
+		while let Some(step) = self.steps.front().copied() {
+			let is_done = match step {
+				Step::Hook(hook) => self.advance_hook(hook),
+				Step::Quit => self.advance_quit(),
+				Step::Start => {
+					self.launch();
+					true
 				}
+			};
+			if !is_done {
+				return;
 			}
+			self.steps.pop_front();
 		}
+	}
 
-		let child = if self.config.interpreter.is_none() {
-			let mut command = Command::new(&self.config.command);
-			command
-				.current_dir(&self.working_dir)
-				.args(self.config.args.as_ref().unwrap_or(&vec![]))
-				.envs(self.config.envs.as_ref().unwrap_or(&vec![]).clone());
-
-			if self.log_dir.is_some() {
-				let log_dir = self.log_dir.as_ref().unwrap();
-
-				let output_location = Path::new(log_dir).join("output.log");
-				let error_location = Path::new(log_dir).join("error.log");
-
-				let output = OpenOptions::new()
-					.create(true)
-					.append(true)
-					.open(output_location);
-				let error = OpenOptions::new()
-					.create(true)
-					.append(true)
-					.open(error_location);
-
-				if output.is_ok() && error.is_ok() {
-					command
-						.stdin(Stdio::null())
-						.stdout(Stdio::from(output.unwrap()))
-						.stderr(Stdio::from(error.unwrap()));
-				} else {
-					command
-						.stdin(Stdio::null())
-						.stdout(Stdio::null())
-						.stderr(Stdio::null());
+	// Waits till the process has been started or stopped
+	pub async fn finish(&mut self) {
+		self.advance();
+		while self.is_busy() {
+			task::sleep(Duration::from_millis(50)).await;
+			self.advance();
+		}
+	}
+
+	// Gives up on starting the process, if it's being started. Stopping it carries on
+	pub fn cancel_start(&mut self) {
+		let start = self.steps.iter().position(|step| {
+			matches!(
+				step,
+				Step::Hook(Hook::PreStart) | Step::Start | Step::Hook(Hook::PostStart)
+			)
+		});
+		if let Some(start) = start {
+			if start == 0 {
+				if let Some(mut hook) = self.hook.take() {
+					hook.kill();
 				}
 			}
+			self.steps.truncate(start);
+		}
+	}
 
-			command.spawn()
-		} else {
-			let mut command = Command::new(self.config.interpreter.as_ref().unwrap());
-			command
-				.current_dir(&self.working_dir)
-				.arg(&self.config.command)
-				.args(self.config.args.as_ref().unwrap_or(&vec![]))
-				.envs(self.config.envs.as_ref().unwrap_or(&vec![]).clone());
-
-			if self.log_dir.is_some() {
-				let log_dir = self.log_dir.as_ref().unwrap();
-
-				let output_location = Path::new(log_dir).join("output.log");
-				let error_location = Path::new(log_dir).join("error.log");
-
-				let output = OpenOptions::new()
-					.create(true)
-					.append(true)
-					.open(output_location);
-				let error = OpenOptions::new()
-					.create(true)
-					.append(true)
-					.open(error_location);
-
-				if output.is_ok() && error.is_ok() {
-					command
-						.stdin(Stdio::null())
-						.stdout(Stdio::from(output.unwrap()))
-						.stderr(Stdio::from(error.unwrap()));
-				} else {
-					command
-						.stdin(Stdio::null())
-						.stdout(Stdio::null())
-						.stderr(Stdio::null());
+	fn advance_hook(&mut self, hook: Hook) -> bool {
+		if self.hook.is_none() {
+			let result = hooks::start(
+				hook,
+				&self.name(),
+				&self.config,
+				&self.global_envs,
+				&self.working_dir,
+				&self.log_dir,
+			);
+			match result {
+				Ok(Some(running_hook)) => self.hook = Some(running_hook),
+				Ok(None) => return true,
+				Err(err) => {
+					self.finish_hook(hook, Err(err));
+					return true;
 				}
 			}
+		}
+		match self.hook.as_mut().unwrap().poll() {
+			Some(result) => {
+				self.hook = None;
+				self.finish_hook(hook, result);
+				true
+			}
+			None => false,
+		}
+	}
 
-			command.spawn()
+	fn finish_hook(&mut self, hook: Hook, result: Result<(), String>) {
+		let err = match result {
+			Ok(()) => return,
+			Err(err) => err,
 		};
-		if let Err(err) = child {
+		logger::error(&format!(
+			"The {} hook of '{}' failed: {}",
+			hook,
+			self.name(),
+			err
+		));
+		if let Hook::PreStart = hook {
+			self.fail_start();
+		}
+	}
+
+	// A failed pre_start hook counts as a failed start
+	fn fail_start(&mut self) {
+		self.steps.clear();
+		self.crashes += 1;
+		self.uptime = 0;
+		self.crash_reason = Some(CrashReason::PreStartFailed);
+		self.events.push(EventKind::Errored(format!(
+			"{}",
+			CrashReason::PreStartFailed
+		)));
+		if self.is_oneshot() {
+			self.status = ModuleRunningStatus::Failed;
+			return;
+		}
+		// The run is over, and failed. The job is run again on schedule
+		if self.is_cron_job() {
+			self.last_run = Some(Err(CrashReason::PreStartFailed));
+			return;
+		}
+		// Wait longer after each failure, up to a minute
+		self.start_failures += 1;
+		let delay = (1000 << (self.start_failures - 1).min(6)).min(60000);
+		self.retry_at = get_current_time() + delay;
+		logger::info(&format!(
+			"Starting '{}' again in {}s",
+			self.name(),
+			delay / 1000
+		));
+	}
+
+	fn advance_quit(&mut self) -> bool {
+		let quit_sent_at = match self.quit_sent_at {
+			Some(quit_sent_at) => quit_sent_at,
+			None => {
+				if !self.is_process_running() {
+					return true;
+				}
+				self.send_quit_signal();
+				self.quit_sent_at = Some(get_current_time());
+				return false;
+			}
+		};
+		if !self.is_process_running() {
+			self.quit_sent_at = None;
+			return true;
+		}
+		// Give the process some time to die
+		if get_current_time() <= quit_sent_at + 1000 {
+			return false;
+		}
+		// It's been trying to quit for more than 1 second. Kill it
+		logger::info(&format!("Killing process: {}", self.name()));
+		self.kill();
+		self.quit_sent_at = None;
+		true
+	}
+
+	// Runs the module (if it's a cron job) or restarts it (if it has a restart_cron) on a schedule
+	pub fn schedule(&mut self, schedule: Schedule) {
+		self.schedule = Some(schedule);
+		self.next_run = self.get_next_run();
+	}
+
+	pub fn is_cron_job(&self) -> bool {
+		self.config.cron.is_some()
+	}
+
+	pub fn is_oneshot(&self) -> bool {
+		self.config.module_type == Some(ModuleType::Oneshot)
+	}
+
+	// Whether a oneshot has run to completion (or failed), so that it isn't run again
+	pub fn has_finished(&mut self) -> bool {
+		self.is_oneshot()
+			&& !self.blocked
+			&& !self.is_process_running()
+			&& (self.status == ModuleRunningStatus::Completed
+				|| self.status == ModuleRunningStatus::Failed)
+	}
+
+	pub fn is_blocked(&self) -> bool {
+		self.blocked
+	}
+
+	// A module that depends on one that failed is marked as failed too. It's waiting
+	// again once that one is restarted. Returns whether it's blocked
+	pub fn check_blocked(&mut self, blocked: bool) -> bool {
+		// Anything that's already running is left alone
+		let is_running = self.is_process_running();
+		let blocked = blocked && !is_running;
+		if blocked != self.blocked {
+			self.blocked = blocked;
+			if blocked {
+				logger::error(&format!(
+					"Process '{}' won't be started, since a module it depends on failed",
+					self.name()
+				));
+				self.status = ModuleRunningStatus::Failed;
+			} else if !is_running {
+				self.status = ModuleRunningStatus::Waiting;
+			}
+		}
+		blocked
+	}
+
+	// Starts a cron job's run if it's due, following its overlap policy. Runs
+	// that are due while the modules it depends on aren't ready are skipped
+	pub fn check_cron(&mut self, dependencies_ready: bool) {
+		let is_running = self.is_process_running();
+		let is_due = self.is_due();
+		if is_due && !dependencies_ready {
+			logger::warn(&format!(
+				"Skipping the scheduled run of '{}', since the modules it depends on aren't ready",
+				self.name()
+			));
+		} else if is_due {
+			let overlap = self
+				.config
+				.cron
+				.as_ref()
+				.and_then(|cron| cron.overlap)
+				.unwrap_or(OverlapPolicy::Skip);
+			match overlap {
+				_ if !is_running => self.run_queued = true,
+				OverlapPolicy::Skip => logger::warn(&format!(
+					"Skipping the scheduled run of '{}', since the previous run hasn't finished",
+					self.name()
+				)),
+				OverlapPolicy::Queue => {
+					logger::info(&format!(
+						"Running '{}' once the previous run finishes",
+						self.name()
+					));
+					self.run_queued = true;
+				}
+				OverlapPolicy::Replace => {
+					logger::info(&format!("Stopping the previous run of '{}'", self.name()));
+					self.stop();
+					self.run_queued = true;
+				}
+			}
+		}
+
+		if self.run_queued && dependencies_ready && !self.is_process_running() {
+			self.run_queued = false;
+			logger::info(&format!("Running '{}'", self.name()));
+			self.spawn();
+		}
+	}
+
+	// Checks if a scheduled restart is due
+	pub fn is_restart_due(&mut self) -> bool {
+		if self.config.restart_cron.is_none() || !self.is_due() {
+			return false;
+		}
+		logger::info(&format!("Restarting '{}' on schedule", self.name()));
+		true
+	}
+
+	// Checks if the schedule is due, and moves on to the next time if it is
+	fn is_due(&mut self) -> bool {
+		match self.next_run {
+			Some(next_run) if get_current_time() >= next_run => {
+				self.next_run = self.get_next_run();
+				true
+			}
+			_ => false,
+		}
+	}
+
+	fn get_next_run(&self) -> Option<u64> {
+		self.schedule
+			.as_ref()
+			.and_then(|schedule| schedule.next_after(Local::now()))
+			.map(|time| time.timestamp_millis() as u64)
+	}
+
+	pub fn is_reloading(&self) -> bool {
+		self.reload_queued || self.reload.is_some()
+	}
+
+	pub fn is_reload_running(&self) -> bool {
+		self.reload.is_some()
+	}
+
+	// Starts a replacement for the process, leaving the current one running until it's ready
+	pub fn start_reload(&mut self) {
+		self.reload_queued = false;
+		if !self.is_process_running() {
+			self.respawn();
+			return;
+		}
+
+		logger::info(&format!("Reloading '{}'", self.name()));
+		let mut previous = self.copy();
+		previous.process = self.process.take();
+		#[cfg(target_family = "unix")]
+		{
+			previous.process_group = self.process_group.take();
+		}
+		// Both of them are in the module's cgroup (and share its limits) while they overlap
+		#[cfg(target_os = "linux")]
+		{
+			previous.cgroup = self.cgroup.clone();
+		}
+		self.spawn();
+		self.reload = Some(Box::new(Reload {
+			previous,
+			started_at: get_current_time(),
+		}));
+	}
+
+	// Stops the previous process once its replacement is ready, or goes back to
+	// it if the replacement doesn't work out. Returns true while still waiting
+	pub fn check_reload(&mut self) -> bool {
+		if self.reload.is_none() {
+			return false;
+		}
+
+		// The previous process can quit while its replacement is getting ready. There's
+		// nothing to go back to then, so the replacement takes over right away
+		let previous = &mut self.reload.as_mut().unwrap().previous;
+		if !previous.is_process_running() {
+			let events = previous.take_events();
+			let crash_reason = previous.crash_reason.clone();
+			self.reload = None;
+			for event in events.iter() {
+				if let EventKind::Exited { crashed: true, .. } = event {
+					self.crashes += 1;
+					self.crash_reason = crash_reason.clone();
+				}
+			}
+			self.events.extend(events);
+			logger::warn(&format!(
+				"The previous process for '{}' quit before its replacement was ready",
+				self.name()
+			));
+			self.events.push(EventKind::Reloaded(Ok(())));
+			return false;
+		}
+
+		// The replacement isn't ready till it's done starting (including its post_start hook)
+		let is_starting = self.is_busy();
+		let is_running = is_starting || self.is_process_running();
+		let config = self.config.reload.clone().unwrap_or_default();
+		let reload = self.reload.as_ref().unwrap();
+		let has_timed_out =
+			get_current_time() > reload.started_at + config.timeout.unwrap_or(30000);
+
+		if !is_running {
+			logger::error(&format!(
+				"The replacement for '{}' quit before it was ready. Keeping the previous process",
+				self.name()
+			));
+			self.events.push(EventKind::Reloaded(Err(String::from(
+				"The replacement quit before it was ready",
+			))));
+		} else if has_timed_out {
+			logger::error(&format!(
+				"The replacement for '{}' took too long to be ready. Keeping the previous process",
+				self.name()
+			));
+			self.events.push(EventKind::Reloaded(Err(String::from(
+				"The replacement took too long to be ready",
+			))));
+		} else if !is_starting && self.uptime >= config.grace_period.unwrap_or(1000) {
+			logger::info(&format!("Finished reloading '{}'", self.name()));
+			self.stop_previous();
+			self.events.push(EventKind::Reloaded(Ok(())));
+			return false;
+		} else {
+			return true;
+		}
+
+		// The replacement is stopped in the background, and the previous process takes over again
+		self.cancel_start();
+		let mut replacement = self.copy();
+		replacement.process = self.process.take();
+		#[cfg(target_family = "unix")]
+		{
+			replacement.process_group = self.process_group.take();
+		}
+		#[cfg(target_os = "linux")]
+		{
+			replacement.cgroup = self.cgroup.clone();
+		}
+		replacement.stop();
+		self.retired.push(replacement);
+		let previous = self.reload.take().unwrap().previous;
+		self.restore(previous);
+		false
+	}
+
+	// Starts stopping the process that's being replaced, if the module is being reloaded
+	pub fn stop_previous(&mut self) {
+		if let Some(reload) = self.reload.take() {
+			let mut previous = reload.previous;
+			previous.stop();
+			self.retired.push(previous);
+		}
+	}
+
+	// The processes that were replaced, to be stopped in the background
+	pub fn take_retired(&mut self) -> Vec<ProcessRunner> {
+		std::mem::take(&mut self.retired)
+	}
+
+	// Goes back to the process that was being replaced
+	fn restore(&mut self, mut previous: ProcessRunner) {
+		self.process = previous.process.take();
+		#[cfg(target_family = "unix")]
+		{
+			self.process_group = previous.process_group.take();
+		}
+		#[cfg(target_os = "linux")]
+		{
+			self.cgroup = previous.cgroup.take();
+		}
+		self.last_started_at = previous.last_started_at;
+		self.stopping = false;
+		self.is_process_running();
+	}
+
+	// Starts spawning the process, with its pre_start and post_start hooks
+	fn spawn(&mut self) {
+		self.steps.extend(&[
+			Step::Hook(Hook::PreStart),
+			Step::Start,
+			Step::Hook(Hook::PostStart),
+		]);
+	}
+
+	// Spawns the process, once its pre_start hook has run
+	fn launch(&mut self) {
+		if let Err(err) = self.start_process() {
 			logger::error(&format!(
 				"Error spawing child process '{}': {}",
-				self.config.name, err
+				self.name(),
+				err
 			));
+			self.events.push(EventKind::Errored(err));
+			// There's nothing for the post_start hook to run after
+			self.steps.clear();
 			return;
 		}
-		self.process = Some(child.unwrap());
+		self.start_failures = 0;
+		self.events.push(if self.restarts == 0 {
+			EventKind::Started
+		} else {
+			EventKind::Restarted
+		});
+	}
+
+	// Sets up everything the process needs, and starts it
+	fn start_process(&mut self) -> Result<(), String> {
+		#[cfg_attr(target_family = "windows", allow(unused_variables))]
+		let credentials = credentials::resolve(&self.config)?;
+
+		#[cfg(target_os = "linux")]
+		{
+			let cgroup_config = self
+				.config
+				.limits
+				.as_ref()
+				.and_then(|limits| limits.cgroup.as_ref());
+			// The cgroup is kept for as long as the module is run, and reused when it's restarted
+			if let (None, Some(cgroup_config)) = (&self.cgroup, cgroup_config) {
+				self.cgroup = Some(Cgroup::create(&self.name(), cgroup_config)?);
+			}
+		}
+		#[cfg(not(target_os = "linux"))]
+		{
+			if let Some(limits) = &self.config.limits {
+				if limits.cgroup.is_some() {
+					logger::warn("cgroups are only supported on Linux. Ignoring the cgroup limits");
+				}
+			}
+		}
+
+		#[cfg(target_os = "linux")]
+		let sandbox = Sandbox::new(&self.config, &self.working_dir, &self.log_dir)?;
+
+		let envs = env::get_module_envs(&self.global_envs, &self.config, &self.working_dir)?;
+		// Read the secrets as late as possible, and don't hold on to them
+		let secret_envs =
+			secrets::get_secret_envs(&self.config, &self.working_dir, &self.secrets_store)?;
+
+		let mut command = if let Some(interpreter) = &self.config.interpreter {
+			let mut command = Command::new(interpreter);
+			command.arg(&self.config.command);
+			command
+		} else {
+			Command::new(&self.config.command)
+		};
+		command
+			.current_dir(&self.working_dir)
+			.args(self.config.args.as_ref().unwrap_or(&vec![]));
+
+		#[cfg(target_family = "unix")]
+		{
+			if let Some(credentials) = &credentials {
+				if let Some(user_name) = credentials.user_name() {
+					command.env("USER", &user_name).env("LOGNAME", &user_name);
+				}
+				if let Some(home_dir) = credentials.home_dir() {
+					command.env("HOME", home_dir);
+				}
+			}
+		}
+		command.envs(envs).envs(secret_envs);
+
+		if let Some(log_dir) = &self.log_dir {
+			let output_location = Path::new(log_dir).join("output.log");
+			let error_location = Path::new(log_dir).join("error.log");
+
+			let output = OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(&output_location);
+			let error = OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(&error_location);
+
+			// Let the module own its logs if it's running as a different user
+			#[cfg(target_family = "unix")]
+			{
+				if let Some(credentials) = &credentials {
+					for path in &[Path::new(log_dir), &output_location, &error_location] {
+						if let Err(err) =
+							unistd::chown(*path, Some(credentials.uid), Some(credentials.gid))
+						{
+							logger::warn(&format!(
+								"Error changing the owner of '{}': {}",
+								path.to_string_lossy(),
+								err
+							));
+						}
+					}
+				}
+			}
+
+			if let (Ok(output), Ok(error)) = (output, error) {
+				command
+					.stdin(Stdio::null())
+					.stdout(Stdio::from(output))
+					.stderr(Stdio::from(error));
+			} else {
+				command
+					.stdin(Stdio::null())
+					.stdout(Stdio::null())
+					.stderr(Stdio::null());
+			}
+		}
+
+		// Run the module in a session (and process group) of its own, so that
+		// the entire tree of processes it spawns can be signalled together
+		#[cfg(target_family = "unix")]
+		unsafe {
+			use std::os::unix::process::CommandExt;
+
+			command.pre_exec(|| unistd::setsid().map(|_| ()).map_err(to_io_error));
+
+			#[cfg(target_os = "linux")]
+			{
+				if let Some(cgroup) = &self.cgroup {
+					let procs_path = cgroup.procs_path();
+					command.pre_exec(move || Cgroup::join(&procs_path));
+				}
+			}
+
+			// Set up the sandbox while the process is still privileged
+			#[cfg(target_os = "linux")]
+			{
+				if let Some(sandbox) = &sandbox {
+					let sandbox = sandbox.clone();
+					command.pre_exec(move || sandbox.enter());
+				}
+			}
+
+			if let Some(limits) = &self.config.limits {
+				command.pre_exec(limits::get_rlimit_applier(limits));
+			}
+
+			// Drop down to the module's user and group right before exec-ing it
+			if let Some(credentials) = credentials {
+				command.pre_exec(move || credentials.apply());
+			}
+
+			#[cfg(target_os = "linux")]
+			{
+				if let Some(sandbox) = sandbox {
+					command.pre_exec(move || sandbox.restrict());
+				}
+			}
+		}
+
+		let child = command.spawn().map_err(|err| err.to_string())?;
+		#[cfg(target_family = "unix")]
+		{
+			self.process_group = Some(child.id() as i32);
+		}
+		self.process = Some(child);
 		self.restarts += 1;
 		self.uptime = 0;
+		self.memory_exceeded_since = None;
+		self.stopping = false;
 		self.status = ModuleRunningStatus::Running;
 		self.last_started_at = get_current_time();
+		Ok(())
+	}
+
+	// Runs one of the module's hooks, if it has it. Returns false if it failed
+	pub async fn run_hook(&self, hook: Hook) -> bool {
+		let result = hooks::run(
+			hook,
+			&self.name(),
+			&self.config,
+			&self.global_envs,
+			&self.working_dir,
+			&self.log_dir,
+		)
+		.await;
+		if let Err(err) = &result {
+			logger::error(&format!(
+				"The {} hook of '{}' failed: {}",
+				hook,
+				self.name(),
+				err
+			));
+		}
+		result.is_ok()
+	}
+
+	fn get_crash_reason(&mut self, status: ExitStatus) -> CrashReason {
+		#[cfg(target_os = "linux")]
+		{
+			if let Some(cgroup) = self.cgroup.as_mut() {
+				if cgroup.was_oom_killed() {
+					return CrashReason::OutOfMemory;
+				}
+			}
+		}
+		#[cfg(target_family = "unix")]
+		{
+			use std::os::unix::process::ExitStatusExt;
+			if let Some(signal) = status.signal() {
+				return CrashReason::Signal(signal);
+			}
+		}
+		CrashReason::ExitCode(status.code().unwrap_or(-1))
 	}
 
 	#[cfg(target_family = "unix")]
@@ -190,22 +1011,23 @@ impl ProcessRunner {
 		if self.process.is_none() {
 			return;
 		}
+		self.stopping = true;
 		// Send SIGINT to a process in unix
 		use nix::{
 			sys::signal::{self, Signal},
 			unistd::Pid,
 		};
 
-		// send SIGINT to the child
-		let result = signal::kill(
+		// send SIGINT to the child's process group
+		let result = signal::killpg(
 			Pid::from_raw(self.process.as_ref().unwrap().id() as i32),
 			Signal::SIGINT,
 		);
-		if result.is_err() {
+		if let Err(err) = result {
 			logger::error(&format!(
 				"Error sending SIGINT to child process '{}': {}",
-				self.config.name,
-				result.unwrap_err()
+				self.name(),
+				err
 			));
 		}
 	}
@@ -215,6 +1037,7 @@ impl ProcessRunner {
 		if self.process.is_none() {
 			return;
 		}
+		self.stopping = true;
 		// Send ctrl-c event to a process in windows
 		// Ref: https://blog.codetitans.pl/post/sending-ctrl-c-signal-to-another-application-on-windows/
 		use winapi::um::{
@@ -234,29 +1057,118 @@ impl ProcessRunner {
 		}
 	}
 
+	#[cfg(target_family = "unix")]
+	pub fn kill(&mut self) {
+		if self.process_group.is_none() {
+			return;
+		}
+		use nix::{
+			errno::Errno,
+			sys::signal::{self, Signal},
+			unistd::Pid,
+		};
+
+		// Kill the whole process group. It's only known till the child is reaped,
+		// since its id can be reused by another process once it's empty
+		let result = signal::killpg(Pid::from_raw(self.process_group.unwrap()), Signal::SIGKILL);
+		match result {
+			Ok(_) | Err(nix::Error::Sys(Errno::ESRCH)) => {}
+			Err(err) => logger::error(&format!("Error killing process: {}", err)),
+		}
+	}
+
+	#[cfg(target_family = "windows")]
 	pub fn kill(&mut self) {
 		if self.process.is_none() {
 			return;
 		}
 		let result = self.process.as_mut().unwrap().kill();
-		if result.is_err() {
-			logger::error(&format!("Error killing process: {}", result.unwrap_err()));
+		if let Err(err) = result {
+			logger::error(&format!("Error killing process: {}", err));
 		}
 	}
 
+	pub fn take_events(&mut self) -> Vec<EventKind> {
+		std::mem::take(&mut self.events)
+	}
+
+	pub fn pid(&self) -> Option<u32> {
+		self.process.as_ref().map(|process| process.id())
+	}
+
+	// The pids of the process, of the hook it's running, and of the one
+	// it's replacing if it's being reloaded
+	pub fn pids(&self) -> Vec<u32> {
+		let mut pids: Vec<u32> = self.pid().into_iter().collect();
+		pids.extend(self.hook.as_ref().map(|hook| hook.pid()));
+		pids.extend(self.retired.iter().flat_map(|retired| retired.pids()));
+		if let Some(reload) = &self.reload {
+			pids.extend(reload.previous.pid());
+		}
+		pids
+	}
+
+	pub fn info(&self) -> ProcessInfo {
+		ProcessInfo {
+			module_id: self.module_id,
+			name: self.name(),
+			status: self.status.clone(),
+			reloading: self.is_reloading(),
+			restarts: self.restarts,
+			uptime: self.uptime,
+			crashes: self.crashes,
+			crash_reason: self.crash_reason.clone(),
+			created_at: self.created_at,
+			next_run: self.next_run,
+			last_run: self.last_run.clone(),
+			config: self.config.clone(),
+		}
+	}
+
+	// Everything but the process. Whatever takes over the process takes its group
+	// and cgroup along with it
 	pub fn copy(&self) -> Self {
 		ProcessRunner {
 			process: None,
+			#[cfg(target_family = "unix")]
+			process_group: None,
+			#[cfg(target_os = "linux")]
+			cgroup: None,
+			#[cfg(target_os = "linux")]
+			watcher: None,
 			log_dir: self.log_dir.clone(),
 			working_dir: self.working_dir.clone(),
 			module_id: self.module_id,
+			instance: self.instance,
 			config: self.config.clone(),
 			status: self.status.clone(),
 			restarts: self.restarts,
 			uptime: self.uptime,
 			last_started_at: self.last_started_at,
 			crashes: self.crashes,
+			crash_reason: self.crash_reason.clone(),
 			created_at: self.created_at,
+			global_envs: self.global_envs.clone(),
+			secrets_store: self.secrets_store.clone(),
+			memory_exceeded_since: self.memory_exceeded_since,
+			last_memory_check: self.last_memory_check,
+			stopping: self.stopping,
+			// The copy can't hold on to the previous process, so it
+			// only keeps track of whether the module is being reloaded
+			reload_queued: self.is_reloading(),
+			reload: None,
+			blocked: self.blocked,
+			next_run: self.next_run,
+			last_run: self.last_run.clone(),
+			schedule: self.schedule.clone(),
+			run_queued: self.run_queued,
+			events: vec![],
+			steps: VecDeque::new(),
+			hook: None,
+			quit_sent_at: None,
+			start_failures: self.start_failures,
+			retry_at: self.retry_at,
+			retired: vec![],
 		}
 	}
 }
@@ -267,3 +1179,11 @@ fn get_current_time() -> u64 {
 		.expect("Time went backwards. Wtf?")
 		.as_millis() as u64
 }
+
+#[cfg(target_family = "unix")]
+pub fn to_io_error(err: nix::Error) -> std::io::Error {
+	match err.as_errno() {
+		Some(errno) => std::io::Error::from_raw_os_error(errno as i32),
+		None => std::io::Error::other(format!("{}", err)),
+	}
+}
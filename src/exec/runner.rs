@@ -1,34 +1,54 @@
+#[cfg(target_os = "linux")]
+use crate::exec::{cgroup, subreaper, watcher};
 use crate::{
-	exec::{juno_module, process::ProcessRunner},
-	models::{GuillotineMessage, GuillotineSpecificConfig, ModuleRunnerConfig},
-	utils::logger,
+	exec::{
+		credentials,
+		events::EventLog,
+		hooks::Hook,
+		juno_module::{self, GuillotineModule},
+		notifications::Notifier,
+		process::ProcessRunner,
+		sandbox,
+	},
+	models::{
+		discovery::{self, DiscoveredModule},
+		ConfigError, EventKind, GuillotineMessage, GuillotineSpecificConfig, ModuleRunnerConfig,
+		ModuleRunningStatus, WatchConfig,
+	},
+	utils::{cron::Schedule, env, logger},
 };
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use async_std::{
-	fs::{self, DirEntry},
-	io::Error,
-	net::TcpStream,
-	path::Path,
-	prelude::*,
-	sync::Mutex,
-	task,
-};
+use async_std::{fs, io::Error, net::TcpStream, path::Path, prelude::*, task};
 use futures::{
-	channel::mpsc::unbounded,
+	channel::{mpsc::UnboundedReceiver, oneshot::Sender},
 	future::{self, Either},
 };
 use futures_timer::Delay;
 
-lazy_static! {
-	static ref CLOSE_FLAG: Mutex<bool> = Mutex::new(false);
-}
+// Runs the modules till a Shutdown message is received. With watch_all, every module
+// is restarted when its files change. The Juno module is connected for the supervisor in
+// the slot. Whether Juno could be started is sent to `started`, and if it couldn't,
+// nothing else is.
+// Returns the Shutdown message's sender, to be answered once everything's let go of
+pub async fn run(
+	config: GuillotineSpecificConfig,
+	watch_all: bool,
+	slot: usize,
+	command_receiver: UnboundedReceiver<GuillotineMessage>,
+	started: Sender<Result<(), String>>,
+) -> Option<Sender<()>> {
+	if config.subreaper.unwrap_or(false) {
+		#[cfg(target_os = "linux")]
+		subreaper::enable();
+		#[cfg(not(target_os = "linux"))]
+		logger::warn("Subreapers are only supported on Linux. Ignoring the subreaper option");
+	}
 
-pub async fn run(config: GuillotineSpecificConfig) {
 	let juno_path = config.juno.path.clone();
 	let mut pid = 0;
 
-	let juno_process = if config.juno.connection_type == "unix_socket" {
+	let mut juno_process = if config.juno.connection_type == "unix_socket" {
 		let socket_path = config.juno.socket_path.as_ref().unwrap();
 		ProcessRunner::new(
 			pid,
@@ -104,113 +124,688 @@ pub async fn run(config: GuillotineSpecificConfig) {
 	};
 	pid += 1;
 
-	let tracked_modules = match &config.modules {
-		Some(modules) => {
-			let mut tracked_modules = Vec::new();
-			let modules_path = Path::new(&modules.path);
-			if modules_path.exists().await && modules_path.is_dir().await {
-				// Get all modules and add them to the list
-				let mut dir_iterator = modules_path.read_dir().await.unwrap();
-				while let Some(path) = dir_iterator.next().await {
-					if let Some(module) = get_module_from_path(pid, path, &modules.logs).await {
-						tracked_modules.push(module);
-						pid += 1;
-					}
-				}
+	if config.inject_env.unwrap_or(true) {
+		juno_process.global_envs = env::get_guillotine_envs(
+			&config.juno,
+			juno_process.module_id,
+			&juno_process.config.name,
+			&juno_process.log_dir,
+		);
+	}
+
+	let log_dir = config
+		.modules
+		.as_ref()
+		.and_then(|modules| modules.logs.clone());
+	let mut tracked_modules: Vec<ProcessRunner> = Vec::new();
+	if let Some(modules) = &config.modules {
+		let discovery = discovery::discover_modules(modules, config.overrides.as_ref()).await;
+		for (dir, reason) in discovery.skipped.iter() {
+			logger::info(&format!("Skipping '{}': {}", dir.to_string_lossy(), reason));
+		}
+		for name in discovery.unused_overrides.iter() {
+			logger::warn(&format!(
+				"Not overriding '{}', since there's no module by that name to run",
+				name
+			));
+		}
+		for err in discovery.errors.iter() {
+			logger::error(&format!("Not running module: {}", err));
+		}
+		// Get all modules and add them to the list
+		for module in discovery.modules {
+			if let Some(instances) = get_module_from_path(pid, module, &log_dir).await {
+				track_module(&mut tracked_modules, instances, &mut pid);
 			}
-			Some(tracked_modules)
 		}
-		None => None,
-	};
+	}
+	// Add the processes declared in the config itself
+	for process in config.processes.iter().flatten() {
+		if !process.enabled.unwrap_or(true) {
+			logger::info(&format!("Skipping '{}': disabled", process.name));
+			continue;
+		}
+		if let Some(instances) = get_module_from_config(pid, process.clone(), &log_dir).await {
+			track_module(&mut tracked_modules, instances, &mut pid);
+		}
+	}
 
-	keep_processes_alive(juno_process, config, tracked_modules).await;
-}
+	for module in tracked_modules.iter_mut() {
+		setup_module(module, &config, watch_all);
+	}
+	let mut configs: Vec<&ModuleRunnerConfig> = vec![];
+	for module in tracked_modules.iter() {
+		// Instances share their config
+		if !configs
+			.iter()
+			.any(|config| config.name == module.config.name)
+		{
+			configs.push(&module.config);
+		}
+	}
+	let mut broken: Vec<String> = vec![];
+	for (index, err) in check_dependencies(&configs) {
+		logger::error(&format!(
+			"Process '{}' won't be started: {}",
+			configs[index].name, err
+		));
+		broken.push(configs[index].name.clone());
+	}
+	// Nor will anything that depends on them
+	for name in get_blocked_modules(&configs, &broken) {
+		logger::error(&format!(
+			"Process '{}' won't be started, since a module it depends on won't be",
+			name
+		));
+		broken.push(name);
+	}
+	tracked_modules.retain(|module| !broken.contains(&module.config.name));
+	// Guillotine moves itself into a cgroup of its own to set up the modules' cgroups, which
+	// has to happen before Juno is started in its current one. Any problem with it is
+	// reported when the modules are started
+	#[cfg(target_os = "linux")]
+	{
+		if tracked_modules.iter().any(|module| {
+			module
+				.config
+				.limits
+				.as_ref()
+				.and_then(|limits| limits.cgroup.as_ref())
+				.is_some()
+		}) {
+			cgroup::set_up().ok();
+		}
+	}
+	let tracked_modules = if config.modules.is_none() && config.processes.is_none() {
+		None
+	} else {
+		Some(tracked_modules)
+	};
 
-pub async fn on_exit() {
-	*CLOSE_FLAG.lock().await = true;
+	keep_processes_alive(
+		juno_process,
+		config,
+		tracked_modules,
+		watch_all,
+		slot,
+		command_receiver,
+		started,
+	)
+	.await
 }
 
 async fn get_module_from_path(
 	expected_pid: u64,
-	path: Result<DirEntry, Error>,
+	module: DiscoveredModule,
 	log_dir: &Option<String>,
-) -> Option<ProcessRunner> {
-	if path.is_err() {
-		return None;
-	}
-	let root_path = path.unwrap().path();
-	let module_json = root_path.join("module.json");
+) -> Option<Vec<ProcessRunner>> {
+	let DiscoveredModule {
+		dir: root_path,
+		manifest_path,
+		config,
+	} = module;
 
-	if !module_json.exists().await {
+	// The cwd of a module is relative to its directory
+	let working_dir = match &config.cwd {
+		Some(cwd) => root_path.join(cwd).to_string_lossy().to_string(),
+		None => root_path.to_string_lossy().to_string(),
+	};
+
+	let errors = check_module_config(&config, &working_dir);
+	if !errors.is_empty() {
+		for err in errors {
+			logger::error(&format!(
+				"Not running module '{}': {}",
+				config.name,
+				err.in_file(&manifest_path.to_string_lossy())
+			));
+		}
 		return None;
 	}
 
-	let module_json_contents = fs::read_to_string(module_json).await;
-	if module_json_contents.is_err() {
+	Some(create_runners(expected_pid, config, log_dir, working_dir).await)
+}
+
+async fn get_module_from_config(
+	expected_pid: u64,
+	config: ModuleRunnerConfig,
+	log_dir: &Option<String>,
+) -> Option<Vec<ProcessRunner>> {
+	let working_dir = config.cwd.clone().unwrap();
+
+	let errors = check_module_config(&config, &working_dir);
+	if !errors.is_empty() {
+		for err in errors {
+			logger::error(&format!("Not running process '{}': {}", config.name, err));
+		}
 		return None;
 	}
-	let module_json_contents = module_json_contents.unwrap();
 
-	let config: Result<ModuleRunnerConfig, serde_json::Error> =
-		serde_json::from_str(&module_json_contents);
+	Some(create_runners(expected_pid, config, log_dir, working_dir).await)
+}
 
-	if config.is_err() {
-		return None;
+// Creates the module's runners. Modules run in cluster mode get one per instance
+async fn create_runners(
+	expected_pid: u64,
+	config: ModuleRunnerConfig,
+	log_dir: &Option<String>,
+	working_dir: String,
+) -> Vec<ProcessRunner> {
+	let instances = match config.instances {
+		Some(instances) => instances.get(),
+		None => return vec![create_runner(expected_pid, config, log_dir, working_dir, None).await],
+	};
+	let mut runners = vec![];
+	for instance in 0..instances {
+		runners.push(
+			create_runner(
+				expected_pid + instance as u64,
+				config.clone(),
+				log_dir,
+				working_dir.clone(),
+				Some(instance),
+			)
+			.await,
+		);
 	}
-	let config = config.unwrap();
+	runners
+}
 
-	let runner = if let Some(log_dir) = log_dir {
+async fn create_runner(
+	expected_pid: u64,
+	config: ModuleRunnerConfig,
+	log_dir: &Option<String>,
+	working_dir: String,
+	instance: Option<usize>,
+) -> ProcessRunner {
+	let mut runner = if let Some(log_dir) = log_dir {
 		let main_dir = Path::new(log_dir);
 		if !main_dir.exists().await {
 			fs::create_dir(&main_dir).await.unwrap();
 		}
 
-		let sub_dir = main_dir.join(&config.name);
+		// Each instance logs to its own directory inside the module's
+		let mut sub_dir = main_dir.join(&config.name);
+		if let Some(instance) = instance {
+			sub_dir = sub_dir.join(instance.to_string());
+		}
 		if !sub_dir.exists().await {
-			fs::create_dir(&sub_dir).await.unwrap();
+			fs::create_dir_all(&sub_dir).await.unwrap();
 		}
 		ProcessRunner::new(
 			expected_pid,
-			config.clone(),
+			config,
 			Some(String::from(sub_dir.to_str().unwrap())),
-			root_path.to_str().unwrap().to_string(),
+			working_dir,
 		)
 	} else {
-		ProcessRunner::new(
-			expected_pid,
-			config.clone(),
-			None,
-			root_path.to_str().unwrap().to_string(),
-		)
+		ProcessRunner::new(expected_pid, config, None, working_dir)
+	};
+	runner.instance = instance;
+	runner
+}
+
+// Adds the module's instances to the tracked modules, unless there's already
+// one with the same name (which would share its logs)
+fn track_module(
+	tracked_modules: &mut Vec<ProcessRunner>,
+	instances: Vec<ProcessRunner>,
+	pid: &mut u64,
+) {
+	let name = match instances.first() {
+		Some(instance) => instance.config.name.clone(),
+		None => return,
+	};
+	if tracked_modules
+		.iter()
+		.any(|tracked_module| tracked_module.config.name == name)
+	{
+		logger::error(&format!(
+			"Not running '{}': There's already a module with the same name",
+			name
+		));
+		return;
+	}
+	*pid += instances.len() as u64;
+	tracked_modules.extend(instances);
+}
+
+// Gives the module what it needs from guillotine's config, before it's run
+fn setup_module(module: &mut ProcessRunner, config: &GuillotineSpecificConfig, watch_all: bool) {
+	module.global_envs = vec![];
+	if config.inject_env.unwrap_or(true) && module.config.inject_env.unwrap_or(true) {
+		module.global_envs = env::get_guillotine_envs(
+			&config.juno,
+			module.module_id,
+			&module.config.name,
+			&module.log_dir,
+		);
+	}
+	if let Some(instance) = module.instance {
+		module
+			.global_envs
+			.push((String::from("INSTANCE_INDEX"), instance.to_string()));
+	}
+	let mut global_envs: Vec<(String, String)> =
+		config.env.clone().unwrap_or_default().into_iter().collect();
+	global_envs.sort();
+	module.global_envs.extend(global_envs);
+	module.secrets_store = config.secrets_store.clone();
+
+	// A watch section turns watching on, unless it's disabled
+	let watch = match &module.config.watch {
+		Some(watch) if watch.enabled.unwrap_or(true) => Some(watch.clone()),
+		Some(_) => None,
+		None if watch_all => Some(WatchConfig::default()),
+		None => None,
 	};
+	if let Some(watch) = watch {
+		module.watch(&watch);
+	}
+
+	// Cron jobs are run on their schedule, and other modules are restarted on theirs
+	let schedule = module
+		.config
+		.cron
+		.as_ref()
+		.map(|cron| &cron.schedule)
+		.or(module.config.restart_cron.as_ref())
+		.map(|schedule| Schedule::parse(schedule));
+	if let Some(Ok(schedule)) = schedule {
+		module.schedule(schedule);
+	}
+}
+
+// Changes the number of instances of a module run in cluster mode. Instances
+// are added and removed from the end, so that their indexes stay 0..count-1
+async fn scale_module(
+	processes: &mut Vec<ProcessRunner>,
+	name: &str,
+	count: usize,
+	config: &GuillotineSpecificConfig,
+	watch_all: bool,
+	retiring: &mut Vec<ProcessRunner>,
+) -> Result<(), String> {
+	let template = match processes.iter().find(|process| process.config.name == name) {
+		Some(template) => template,
+		None => return Err(format!("There's no module named '{}'", name)),
+	};
+	if template.instance.is_none() {
+		return Err(format!(
+			"'{}' isn't run in cluster mode. Set `instances` in its config to scale it",
+			name
+		));
+	}
+	if count == 0 {
+		return Err(String::from("A module needs at least 1 instance"));
+	}
+	let current = processes
+		.iter()
+		.filter(|process| process.config.name == name)
+		.count();
+	logger::info(&format!(
+		"Scaling '{}' from {} to {} instance(s)",
+		name, current, count
+	));
+
+	if count > current {
+		let (module_config, working_dir) = (template.config.clone(), template.working_dir.clone());
+		let log_dir = config
+			.modules
+			.as_ref()
+			.and_then(|modules| modules.logs.clone());
+		// New instances get the ids after the highest one in use
+		let module_id = processes
+			.iter()
+			.map(|process| process.module_id)
+			.max()
+			.unwrap_or(0)
+			+ 1;
+		for instance in current..count {
+			let mut runner = create_runner(
+				module_id + (instance - current) as u64,
+				module_config.clone(),
+				&log_dir,
+				working_dir.clone(),
+				Some(instance),
+			)
+			.await;
+			setup_module(&mut runner, config, watch_all);
+			// It's spawned along with any other module that isn't running
+			processes.push(runner);
+		}
+		return Ok(());
+	}
+
+	let is_removed = |process: &ProcessRunner| {
+		process.config.name == name && process.instance.is_some_and(|instance| instance >= count)
+	};
+	let (removed, kept) = processes.drain(..).partition(|process| is_removed(process));
+	*processes = kept;
+	// They're stopped in the background, along with the processes they were replacing
+	for mut process in removed {
+		logger::info(&format!("Quitting process: {}", process.name()));
+		process.stop_previous();
+		process.stop();
+		retiring.extend(process.take_retired());
+		retiring.push(process);
+	}
+	Ok(())
+}
+
+// Checks the parts of a module's config that can't be checked while parsing it.
+// Returns all the problems found
+pub fn check_module_config(config: &ModuleRunnerConfig, working_dir: &str) -> Vec<ConfigError> {
+	let mut errors = vec![];
+
+	if let Err(err) = credentials::resolve(config) {
+		errors.push(ConfigError::new("user", &err));
+	}
+	if let Err(err) = sandbox::validate(config) {
+		errors.push(ConfigError::new("sandbox", &err));
+	}
+	if let Some(watch) = &config.watch {
+		#[cfg(target_os = "linux")]
+		{
+			if let Err(err) = watcher::get_ignore_patterns(watch) {
+				errors.push(ConfigError::new("watch.ignore", &err));
+			}
+		}
+		for (index, path) in watch.paths.iter().flatten().enumerate() {
+			if !std::path::Path::new(working_dir).join(path).exists() {
+				errors.push(ConfigError::new(
+					&format!("watch.paths[{}]", index),
+					&format!("'{}' doesn't exist", path),
+				));
+			}
+		}
+	}
+	if let Some(cron) = &config.cron {
+		if let Err(err) = Schedule::parse(&cron.schedule) {
+			errors.push(ConfigError::new("cron.schedule", &err));
+		}
+		if config.restart_cron.is_some() {
+			errors.push(ConfigError::new(
+				"restart_cron",
+				"Cron jobs can't be restarted on a schedule, since they aren't kept running",
+			));
+		}
+	}
+	if let Some(restart_cron) = &config.restart_cron {
+		if let Err(err) = Schedule::parse(restart_cron) {
+			errors.push(ConfigError::new("restart_cron", &err));
+		}
+	}
+	for (index, env_file) in config.env_file.iter().flatten().enumerate() {
+		if !std::path::Path::new(working_dir).join(env_file).is_file() {
+			errors.push(ConfigError::new(
+				&format!("env_file[{}]", index),
+				&format!("'{}' doesn't exist", env_file),
+			));
+		}
+	}
+	let mut secrets: Vec<_> = config.secrets.iter().flatten().collect();
+	secrets.sort_by_key(|(name, _)| *name);
+	for (name, secret) in secrets {
+		match (&secret.file, &secret.store) {
+			(Some(file), None) => {
+				if !std::path::Path::new(working_dir).join(file).is_file() {
+					errors.push(ConfigError::new(
+						&format!("secrets.{}.file", name),
+						&format!("'{}' doesn't exist", file),
+					));
+				}
+			}
+			(None, Some(_)) => {}
+			_ => errors.push(ConfigError::new(
+				&format!("secrets.{}", name),
+				"Expected either `file` or `store`",
+			)),
+		}
+	}
+
+	errors
+}
+
+// Checks that the modules each module depends on exist, and don't depend on it in turn.
+// Returns the index of the module each problem was found in, along with the problem
+pub fn check_dependencies(modules: &[&ModuleRunnerConfig]) -> Vec<(usize, ConfigError)> {
+	let mut errors = vec![];
+	for (index, module) in modules.iter().enumerate() {
+		for (dependency_index, dependency) in module.depends_on.iter().flatten().enumerate() {
+			let field = format!("depends_on[{}]", dependency_index);
+			if *dependency == module.name {
+				errors.push((
+					index,
+					ConfigError::new(&field, "A module can't depend on itself"),
+				));
+			} else if !modules.iter().any(|module| module.name == *dependency) {
+				errors.push((
+					index,
+					ConfigError::new(
+						&field,
+						&format!("There's no module named '{}' to depend on", dependency),
+					),
+				));
+			} else if let Some(path) = find_dependency_path(modules, dependency, &module.name) {
+				errors.push((
+					index,
+					ConfigError::new(
+						&field,
+						&format!(
+							"Depending on '{}' creates a loop: {} -> {}",
+							dependency,
+							module.name,
+							path.join(" -> ")
+						),
+					),
+				));
+			}
+		}
+	}
+	errors
+}
+
+// The chain of dependencies that leads from one module to another, if there is one
+fn find_dependency_path(
+	modules: &[&ModuleRunnerConfig],
+	from: &str,
+	to: &str,
+) -> Option<Vec<String>> {
+	let mut visited = vec![from.to_string()];
+	let mut paths = vec![vec![from.to_string()]];
+	while let Some(path) = paths.pop() {
+		let name = path.last().unwrap();
+		if name == to {
+			return Some(path);
+		}
+		let module = modules.iter().find(|module| module.name == *name);
+		for dependency in module
+			.and_then(|module| module.depends_on.as_ref())
+			.into_iter()
+			.flatten()
+		{
+			if !visited.contains(dependency) {
+				visited.push(dependency.clone());
+				let mut path = path.clone();
+				path.push(dependency.clone());
+				paths.push(path);
+			}
+		}
+	}
+	None
+}
+
+// The modules that depend on any of the failed ones, directly or through others
+fn get_blocked_modules(modules: &[&ModuleRunnerConfig], failed: &[String]) -> Vec<String> {
+	let mut blocked: Vec<String> = vec![];
+	loop {
+		let mut changed = false;
+		for module in modules {
+			if failed.contains(&module.name) || blocked.contains(&module.name) {
+				continue;
+			}
+			if module
+				.depends_on
+				.iter()
+				.flatten()
+				.any(|dependency| failed.contains(dependency) || blocked.contains(dependency))
+			{
+				blocked.push(module.name.clone());
+				changed = true;
+			}
+		}
+		if !changed {
+			return blocked;
+		}
+	}
+}
 
-	Some(runner)
+// The names of the modules that are ready to be depended on. That is, every one
+// of their instances is running, or (for oneshots) has completed
+fn get_ready_modules(processes: &[ProcessRunner]) -> Vec<String> {
+	let mut ready: Vec<String> = processes
+		.iter()
+		.map(|process| process.config.name.clone())
+		.collect();
+	for process in processes {
+		let is_ready = match process.status {
+			// Cron jobs only run now and then, so there's nothing to wait for
+			_ if process.is_cron_job() => true,
+			ModuleRunningStatus::Running => !process.is_oneshot(),
+			ModuleRunningStatus::Completed => true,
+			_ => false,
+		};
+		if !is_ready {
+			ready.retain(|name| *name != process.config.name);
+		}
+	}
+	ready
+}
+
+// The names of the oneshots that failed, and aren't being run again. Not counting
+// the ones that were never run, since they depend on one of them
+fn get_failed_oneshots(processes: &[ProcessRunner]) -> Vec<String> {
+	processes
+		.iter()
+		.filter(|process| {
+			process.is_oneshot()
+				&& !process.is_blocked()
+				&& !process.is_busy()
+				&& process.status == ModuleRunningStatus::Failed
+		})
+		.map(|process| process.config.name.clone())
+		.collect()
+}
+
+// Records what has happened to the processes, and triggers the Juno hooks for it
+fn publish_events(
+	event_log: &mut EventLog,
+	notifier: &mut Notifier,
+	module: &GuillotineModule,
+	processes: &mut [ProcessRunner],
+) {
+	for process in processes.iter_mut() {
+		for kind in process.take_events() {
+			let event = event_log.add(process.module_id, process.name(), kind);
+			module.trigger(&event);
+			notifier.notify(&event);
+		}
+	}
+}
+
+fn publish_juno_restart(
+	event_log: &mut EventLog,
+	notifier: &mut Notifier,
+	module: &GuillotineModule,
+	juno_process: &mut ProcessRunner,
+) {
+	// The hooks for juno's own exit couldn't have been triggered while it was down
+	juno_process.take_events();
+	let event = event_log.add(
+		juno_process.module_id,
+		juno_process.name(),
+		EventKind::JunoRestarted,
+	);
+	module.trigger(&event);
+	notifier.notify(&event);
+}
+
+// Carries on stopping the processes that were replaced or removed, and
+// lets go of them once they've stopped
+fn advance_retiring(retiring: &mut Vec<ProcessRunner>) {
+	for process in retiring.iter_mut() {
+		process.advance();
+		// Clean up anything left behind in its process group
+		if !process.is_busy() {
+			process.kill();
+		}
+	}
+	retiring.retain_mut(|process| process.is_busy() || process.is_process_running());
+}
+
+// Starts reloading the modules that are waiting to be. Only one
+// instance of a module is reloaded at a time
+fn start_queued_reloads(processes: &mut [ProcessRunner]) {
+	let mut reloading: Vec<String> = processes
+		.iter()
+		.filter(|process| process.is_reload_running())
+		.map(|process| process.config.name.clone())
+		.collect();
+	for process in processes.iter_mut() {
+		// Modules that are being started or stopped are reloaded once they're done
+		if !process.reload_queued || process.is_busy() || reloading.contains(&process.config.name) {
+			continue;
+		}
+		reloading.push(process.config.name.clone());
+		process.start_reload();
+	}
 }
 
 async fn keep_processes_alive(
 	mut juno_process: ProcessRunner,
 	juno_config: GuillotineSpecificConfig,
 	mut processes: Option<Vec<ProcessRunner>>,
-) {
+	watch_all: bool,
+	slot: usize,
+	mut command_receiver: UnboundedReceiver<GuillotineMessage>,
+	started: Sender<Result<(), String>>,
+) -> Option<Sender<()>> {
 	// Spawn juno before spawing any modules
-	while !juno_process.is_process_running() {
-		juno_process.respawn().await;
-		ensure_juno_initialized(juno_config.clone()).await;
+	juno_process.respawn();
+	juno_process.finish().await;
+	if let Err(err) = ensure_juno_initialized(&juno_config, &mut juno_process).await {
+		started.send(Err(err)).ok();
+		return None;
 	}
 	// Initialize the guillotine juno module
-	let (mut sender, mut command_receiver) = unbounded::<GuillotineMessage>();
-	let mut module = juno_module::setup_module(juno_config.clone(), sender).await;
+	let mut module = match juno_module::setup_module(juno_config.clone(), slot).await {
+		Ok(module) => module,
+		Err(err) => {
+			// Juno's of no use without it
+			juno_process.kill();
+			while juno_process.is_process_running() {
+				task::sleep(Duration::from_millis(100)).await;
+			}
+			started.send(Err(err)).ok();
+			return None;
+		}
+	};
+	// Nothing's listening for how juno itself started
+	juno_process.take_events();
+	let mut event_log = EventLog::default();
+	let mut notifier = Notifier::new(juno_config.notifications.clone());
+	// Processes that were replaced or removed, and are being stopped
+	let mut retiring: Vec<ProcessRunner> = vec![];
 
 	let mut timer_future = Delay::new(Duration::from_millis(100));
 	let mut command_future = command_receiver.next();
-	loop {
+	started.send(Ok(())).ok();
+	// The loop only ends when it's asked to shut down
+	let shutdown_sender = loop {
 		let selection = future::select(timer_future, command_future).await;
 		match selection {
 			Either::Left((_, next_command_future)) => {
-				if *CLOSE_FLAG.lock().await {
-					break;
-				}
-
 				// Timer expired
 				command_future = next_command_future;
 				timer_future = Delay::new(Duration::from_millis(100));
@@ -218,29 +813,93 @@ async fn keep_processes_alive(
 				// Make sure juno is running before checking any other modules
 				if !juno_process.is_process_running() {
 					module.close().await;
-					drop(module);
-
-					juno_process.respawn().await;
-					ensure_juno_initialized(juno_config.clone()).await;
 
-					let channel = unbounded::<GuillotineMessage>();
-					sender = channel.0;
-					command_receiver = channel.1;
+					module = reconnect_juno(&juno_config, &mut juno_process, slot).await;
+					publish_juno_restart(&mut event_log, &mut notifier, &module, &mut juno_process);
+				}
 
-					command_future = command_receiver.next();
-					module = juno_module::setup_module(juno_config.clone(), sender).await;
+				#[cfg(target_os = "linux")]
+				{
+					if juno_config.subreaper.unwrap_or(false) {
+						let notifying = notifier.lock_pids();
+						subreaper::reap_orphans(&get_tracked_pids(
+							&juno_process,
+							&processes,
+							&retiring,
+							&notifying,
+						));
+					}
 				}
 
+				advance_retiring(&mut retiring);
 				if processes.is_none() {
 					continue;
 				}
 				let processes = processes.as_mut().unwrap();
+				let ready_modules = get_ready_modules(processes);
+				let blocked_modules = get_blocked_modules(
+					&processes
+						.iter()
+						.map(|process| &process.config)
+						.collect::<Vec<_>>(),
+					&get_failed_oneshots(processes),
+				);
 				for module in processes.iter_mut() {
+					// Hooks are run in the background. Carry on with whatever was waiting on them
+					module.advance();
+					// Leave modules alone while they're being reloaded
+					if module.check_reload() {
+						continue;
+					}
+					// Or while they're being started or stopped
+					if module.is_busy() {
+						continue;
+					}
+					let dependencies_ready = module
+						.config
+						.depends_on
+						.iter()
+						.flatten()
+						.all(|dependency| ready_modules.contains(dependency));
+					// Oneshots aren't run again once they've finished, unless they're restarted
+					if module.has_finished() {
+						continue;
+					}
+					// Nor is anything that depends on a oneshot that failed
+					if module.check_blocked(blocked_modules.contains(&module.config.name)) {
+						continue;
+					}
+					// Cron jobs are only run when they're due
+					if module.is_cron_job() {
+						module.check_cron(dependencies_ready);
+						continue;
+					}
+					// Modules are only started once the modules they depend on are ready
+					if !module.is_process_running() && !dependencies_ready {
+						module.status = ModuleRunningStatus::Waiting;
+						continue;
+					}
+					// Modules whose pre_start hook failed wait a while before they're tried again
+					if !module.is_process_running() && module.is_backing_off() {
+						continue;
+					}
 					// If a module isn't running, respawn it. Simple.
-					if !module.is_process_running() {
-						module.respawn().await;
+					// A due restart is checked first, so that it's never put off
+					if module.is_restart_due()
+						|| !module.is_process_running()
+						|| module.has_exceeded_memory_threshold()
+						|| module.has_changed_files()
+					{
+						module.respawn();
 					}
+					// Start anything that doesn't have to wait on a hook right away
+					module.advance();
+				}
+				start_queued_reloads(processes);
+				for process in processes.iter_mut() {
+					retiring.extend(process.take_retired());
 				}
+				publish_events(&mut event_log, &mut notifier, &module, processes);
 			}
 			Either::Right((command_value, next_timer_future)) => {
 				// Got a command from juno
@@ -250,13 +909,13 @@ async fn keep_processes_alive(
 				match command_value {
 					Some(cmd) => match cmd {
 						GuillotineMessage::ListProcesses(sender) => {
-							let mut runners = vec![juno_process.copy()];
+							let mut runners = vec![juno_process.info()];
 							if processes.is_some() {
 								processes
 									.as_ref()
 									.unwrap()
 									.iter()
-									.for_each(|process| runners.push(process.copy()));
+									.for_each(|process| runners.push(process.info()));
 							}
 							sender.send(runners).unwrap();
 						}
@@ -269,18 +928,15 @@ async fn keep_processes_alive(
 							if pid == 0 {
 								response_sender.send(true).unwrap();
 								module.close().await;
-								drop(module);
-
-								juno_process.respawn().await;
-								ensure_juno_initialized(juno_config.clone()).await;
-
-								let channel = unbounded::<GuillotineMessage>();
-								sender = channel.0;
-								command_receiver = channel.1;
 
-								command_future = command_receiver.next();
 								module =
-									juno_module::setup_module(juno_config.clone(), sender).await;
+									reconnect_juno(&juno_config, &mut juno_process, slot).await;
+								publish_juno_restart(
+									&mut event_log,
+									&mut notifier,
+									&module,
+									&mut juno_process,
+								);
 								continue;
 							}
 
@@ -293,9 +949,54 @@ async fn keep_processes_alive(
 								response_sender.send(false).unwrap();
 								continue;
 							}
-							module.unwrap().respawn().await;
+							module.unwrap().respawn();
 							response_sender.send(true).unwrap();
 						}
+						GuillotineMessage::ScaleProcess(name, count, response_sender) => {
+							let result = match processes.as_mut() {
+								Some(processes) => {
+									scale_module(
+										processes,
+										&name,
+										count,
+										&juno_config,
+										watch_all,
+										&mut retiring,
+									)
+									.await
+								}
+								None => Err(format!("There's no module named '{}'", name)),
+							};
+							response_sender.send(result).unwrap();
+						}
+						GuillotineMessage::ListEvents(after, sender) => {
+							sender.send(event_log.list(after)).unwrap();
+						}
+						GuillotineMessage::Subscribe(subscriber) => {
+							event_log.subscribe(subscriber);
+						}
+						GuillotineMessage::Shutdown(response_sender) => {
+							break response_sender;
+						}
+						GuillotineMessage::ReloadProcess(name, response_sender) => {
+							let mut instances = 0;
+							if let Some(processes) = processes.as_mut() {
+								for process in processes
+									.iter_mut()
+									.filter(|process| process.config.name == name)
+								{
+									process.reload_queued = true;
+									instances += 1;
+								}
+							}
+							response_sender
+								.send(if instances == 0 {
+									Err(format!("There's no module named '{}'", name))
+								} else {
+									Ok(instances)
+								})
+								.unwrap();
+						}
 						_ => {}
 					},
 					None => {
@@ -304,13 +1005,43 @@ async fn keep_processes_alive(
 				}
 			}
 		}
-	}
+	};
 
 	// Execute exit actions
 	// Kill all modules first
+	let mut stopped_modules = vec![];
+	if let Some(processes) = processes.as_mut() {
+		for module in processes.iter_mut() {
+			// Modules that are being started aren't started after all
+			module.cancel_start();
+			module.stop_previous();
+			retiring.extend(module.take_retired());
+		}
+		// Let the ones that were already being stopped finish stopping
+		loop {
+			for module in processes.iter_mut().chain(retiring.iter_mut()) {
+				module.advance();
+			}
+			if !processes
+				.iter()
+				.chain(retiring.iter())
+				.any(|module| module.is_busy())
+			{
+				break;
+			}
+			task::sleep(Duration::from_millis(50)).await;
+		}
+		// Every module's pre_stop hook is run before any of them are asked to quit
+		for (index, module) in processes.iter_mut().enumerate() {
+			if module.is_process_running() {
+				module.run_hook(Hook::PreStop).await;
+				stopped_modules.push(index);
+			}
+		}
+	}
 	if processes.is_some() {
 		processes.as_mut().unwrap().iter_mut().for_each(|module| {
-			logger::info(&format!("Quitting process: {}", module.config.name));
+			logger::info(&format!("Quitting process: {}", module.name()));
 			module.send_quit_signal();
 		});
 		let quit_time = get_current_millis();
@@ -329,14 +1060,23 @@ async fn keep_processes_alive(
 			// If some of the processes are running, check if they've been given enough time.
 			if get_current_millis() > quit_time + 1000 {
 				// They've been trying to quit for more than 1 second. Kill them all and quit
-				processes.unwrap().iter_mut().for_each(|module| {
-					logger::info(&format!("Killing process: {}", module.config.name));
+				processes.as_mut().unwrap().iter_mut().for_each(|module| {
+					logger::info(&format!("Killing process: {}", module.name()));
 					module.kill();
 				});
 				break;
 			}
 		}
 	}
+	if let Some(processes) = processes.as_ref() {
+		for index in stopped_modules {
+			processes[index].run_hook(Hook::PostStop).await;
+		}
+	}
+
+	// Disconnect from juno while it's still up. Otherwise the connection spins on the
+	// closed socket for as long as the process is around
+	module.close().await;
 
 	// Now quit juno similarly
 	logger::info(&format!("Quitting process: {}", juno_process.config.name));
@@ -358,33 +1098,124 @@ async fn keep_processes_alive(
 			break;
 		}
 	}
+
+	// Let the notifications about all of that go out before anything's killed
+	notifier.wait();
+
+	// Make sure nothing is left behind in the groups of the processes that are yet to be reaped
+	#[cfg(target_family = "unix")]
+	{
+		if let Some(processes) = processes.as_mut() {
+			processes.iter_mut().for_each(|module| module.kill());
+		}
+		retiring.iter_mut().for_each(|process| process.kill());
+		juno_process.kill();
+	}
+
+	#[cfg(target_os = "linux")]
+	{
+		if juno_config.subreaper.unwrap_or(false) {
+			let notifying = notifier.lock_pids();
+			subreaper::kill_orphans(&get_tracked_pids(
+				&juno_process,
+				&processes,
+				&retiring,
+				&notifying,
+			));
+		}
+	}
+
+	Some(shutdown_sender)
+}
+
+#[cfg(target_os = "linux")]
+fn get_tracked_pids(
+	juno_process: &ProcessRunner,
+	processes: &Option<Vec<ProcessRunner>>,
+	retiring: &[ProcessRunner],
+	notifying: &std::collections::HashSet<u32>,
+) -> Vec<u32> {
+	let mut pids: Vec<u32> = juno_process.pid().into_iter().collect();
+	if let Some(processes) = processes {
+		pids.extend(processes.iter().flat_map(|process| process.pids()));
+	}
+	pids.extend(retiring.iter().flat_map(|process| process.pids()));
+	pids.extend(notifying);
+	pids
 }
 
-async fn ensure_juno_initialized(config: GuillotineSpecificConfig) {
-	if config.juno.port.is_some() {
-		let port = config.juno.port.unwrap();
-		// Keep attempting to connect to the port until you can connect
-		let port = format!("127.0.0.1:{}", port);
-		let mut connection = TcpStream::connect(&port).await;
-		while connection.is_err() {
-			// If connection failed, wait and try again
-			Delay::new(Duration::from_millis(250)).await;
-			connection = TcpStream::connect(&port).await;
+// Nothing else works without Juno, so it's tried again till it's back
+async fn restart_juno(config: &GuillotineSpecificConfig, juno_process: &mut ProcessRunner) {
+	loop {
+		juno_process.respawn();
+		juno_process.finish().await;
+		match ensure_juno_initialized(config, juno_process).await {
+			Ok(()) => return,
+			Err(err) => {
+				logger::error(&err);
+				task::sleep(Duration::from_secs(1)).await;
+			}
 		}
-	} else {
-		let unix_socket = config
-			.juno
-			.socket_path
-			.unwrap_or_else(|| String::from("./juno.sock"));
-		let mut connection = connect_to_unix_socket(&unix_socket).await;
-		while connection.is_err() {
-			// If connection failed, wait and try again
-			Delay::new(Duration::from_millis(250)).await;
-			connection = connect_to_unix_socket(&unix_socket).await;
+	}
+}
+
+// Restarts Juno, and connects the Juno module to it again
+async fn reconnect_juno(
+	config: &GuillotineSpecificConfig,
+	juno_process: &mut ProcessRunner,
+	slot: usize,
+) -> GuillotineModule {
+	loop {
+		restart_juno(config, juno_process).await;
+		match juno_module::setup_module(config.clone(), slot).await {
+			Ok(module) => return module,
+			Err(err) => logger::error(&err),
 		}
 	}
 }
 
+// Waits till Juno can be connected to. Fails if it couldn't be started, or quits before then
+async fn ensure_juno_initialized(
+	config: &GuillotineSpecificConfig,
+	juno_process: &mut ProcessRunner,
+) -> Result<(), String> {
+	loop {
+		if !juno_process.is_process_running() {
+			let reason = juno_process
+				.take_events()
+				.into_iter()
+				.find_map(|event| match event {
+					EventKind::Errored(err) => Some(err),
+					EventKind::Exited {
+						result: Err(crash_reason),
+						..
+					} => Some(crash_reason.to_string()),
+					_ => None,
+				})
+				.unwrap_or_else(|| String::from("it quit"));
+			return Err(format!("Juno couldn't be started: {}", reason));
+		}
+
+		let connection = if let Some(port) = config.juno.port {
+			TcpStream::connect(format!("127.0.0.1:{}", port))
+				.await
+				.map(|_| ())
+		} else {
+			let unix_socket = config
+				.juno
+				.socket_path
+				.clone()
+				.unwrap_or_else(|| String::from("./juno.sock"));
+			connect_to_unix_socket(&unix_socket).await
+		};
+		if connection.is_ok() {
+			return Ok(());
+		}
+		// If connection failed, wait and try again
+		Delay::new(Duration::from_millis(250)).await;
+	}
+}
+
 #[cfg(target_family = "unix")]
 async fn connect_to_unix_socket(socket_path: &str) -> Result<(), Error> {
 	async_std::os::unix::net::UnixStream::connect(socket_path).await?;
@@ -401,3 +1232,119 @@ fn get_current_millis() -> u128 {
 		.expect("Time went backwards. Wtf?")
 		.as_millis()
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn module(name: &str, depends_on: &[&str]) -> ModuleRunnerConfig {
+		let mut config = ModuleRunnerConfig::juno_default(String::from("true"), vec![]);
+		config.name = name.to_string();
+		config.depends_on = Some(depends_on.iter().map(|name| name.to_string()).collect());
+		config
+	}
+
+	fn errors(modules: &[ModuleRunnerConfig]) -> Vec<(String, String)> {
+		check_dependencies(&modules.iter().collect::<Vec<_>>())
+			.into_iter()
+			.map(|(index, err)| (modules[index].name.clone(), err.message))
+			.collect()
+	}
+
+	fn blocked(modules: &[ModuleRunnerConfig], failed: &[&str]) -> Vec<String> {
+		let failed: Vec<String> = failed.iter().map(|name| name.to_string()).collect();
+		get_blocked_modules(&modules.iter().collect::<Vec<_>>(), &failed)
+	}
+
+	#[test]
+	fn accepts_dependencies() {
+		let modules = [
+			module("db", &[]),
+			module("migrate", &["db"]),
+			module("api", &["db", "migrate"]),
+		];
+		assert!(errors(&modules).is_empty());
+	}
+
+	#[test]
+	fn finds_missing_dependencies() {
+		let modules = [module("api", &["db"])];
+		assert_eq!(
+			errors(&modules),
+			vec![(
+				String::from("api"),
+				String::from("There's no module named 'db' to depend on")
+			)]
+		);
+	}
+
+	#[test]
+	fn finds_modules_depending_on_themselves() {
+		let modules = [module("api", &["api"])];
+		assert_eq!(
+			errors(&modules),
+			vec![(
+				String::from("api"),
+				String::from("A module can't depend on itself")
+			)]
+		);
+	}
+
+	#[test]
+	fn finds_loops() {
+		let modules = [
+			module("a", &["b"]),
+			module("b", &["c"]),
+			module("c", &["a"]),
+			module("d", &["a"]),
+		];
+		assert_eq!(
+			errors(&modules),
+			vec![
+				(
+					String::from("a"),
+					String::from("Depending on 'b' creates a loop: a -> b -> c -> a")
+				),
+				(
+					String::from("b"),
+					String::from("Depending on 'c' creates a loop: b -> c -> a -> b")
+				),
+				(
+					String::from("c"),
+					String::from("Depending on 'a' creates a loop: c -> a -> b -> c")
+				),
+			]
+		);
+		// Depending on a loop isn't a loop in itself, but it won't be started either
+		let broken = ["a", "b", "c"];
+		assert_eq!(blocked(&modules, &broken), vec![String::from("d")]);
+	}
+
+	#[test]
+	fn blocks_modules_depending_on_failed_oneshots() {
+		let modules = [
+			module("migrate", &[]),
+			module("api", &["migrate"]),
+			module("web", &["api"]),
+			module("cache", &[]),
+		];
+		assert_eq!(
+			blocked(&modules, &["migrate"]),
+			vec![String::from("api"), String::from("web")]
+		);
+		assert!(blocked(&modules, &[]).is_empty());
+	}
+
+	#[test]
+	fn blocks_modules_declared_before_their_dependencies() {
+		let modules = [
+			module("web", &["api"]),
+			module("api", &["migrate"]),
+			module("migrate", &[]),
+		];
+		assert_eq!(
+			blocked(&modules, &["migrate"]),
+			vec![String::from("api"), String::from("web")]
+		);
+	}
+}
@@ -0,0 +1,346 @@
+use crate::models::ModuleRunnerConfig;
+
+#[cfg(target_os = "linux")]
+use crate::{
+	exec::{
+		process::to_io_error,
+		seccomp::{self, SockFilter},
+	},
+	models::SandboxNamespace,
+};
+#[cfg(target_os = "linux")]
+use nix::{
+	mount::{self, MsFlags},
+	sched::{self, CloneFlags},
+	sys::wait::{self, WaitStatus},
+	unistd::{self, ForkResult},
+};
+#[cfg(target_os = "linux")]
+use std::{
+	ffi::CString,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+// Everything needed to sandbox a module, worked out before forking so that
+// nothing needs to be allocated in the child
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+	namespaces: CloneFlags,
+	read_only_root: bool,
+	// Every mount that's remounted read-only, with the flags it already has
+	read_only_mounts: Vec<(CString, MsFlags)>,
+	writable_paths: Vec<CString>,
+	chroot: Option<CString>,
+	// The working directory, relative to the chroot (if any)
+	working_dir: CString,
+	proc_dir: CString,
+	no_new_privs: bool,
+	seccomp_filter: Option<Vec<SockFilter>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Sandbox {
+	pub fn new(
+		config: &ModuleRunnerConfig,
+		working_dir: &str,
+		log_dir: &Option<String>,
+	) -> Result<Option<Self>, String> {
+		let sandbox = match &config.sandbox {
+			Some(sandbox) => sandbox,
+			None => return Ok(None),
+		};
+
+		let mut namespaces = CloneFlags::empty();
+		for namespace in sandbox.namespaces.as_ref().unwrap_or(&vec![]) {
+			namespaces |= match namespace {
+				SandboxNamespace::Mount => CloneFlags::CLONE_NEWNS,
+				// The PID namespace gets its own /proc, which needs a mount namespace
+				SandboxNamespace::Pid => CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS,
+				SandboxNamespace::Network => CloneFlags::CLONE_NEWNET,
+				SandboxNamespace::Ipc => CloneFlags::CLONE_NEWIPC,
+			};
+		}
+		let read_only_root = sandbox.read_only_root.unwrap_or(false);
+		if read_only_root || sandbox.chroot.is_some() {
+			namespaces |= CloneFlags::CLONE_NEWNS;
+		}
+		if !namespaces.is_empty() && !unistd::geteuid().is_root() {
+			return Err(format!(
+				"Guillotine needs to run as root to sandbox '{}' in namespaces",
+				config.name
+			));
+		}
+
+		// Bind mounts need absolute paths
+		let working_dir = fs::canonicalize(working_dir)
+			.map(|dir| dir.to_string_lossy().to_string())
+			.unwrap_or_else(|_| working_dir.to_string());
+
+		let mut writable_paths = vec![working_dir.clone()];
+		if let Some(log_dir) = log_dir {
+			writable_paths.push(log_dir.clone());
+		}
+		writable_paths.extend(sandbox.writable_paths.clone().unwrap_or_default());
+
+		// Mounts under the root (like /home or /tmp) aren't affected by remounting
+		// it, so each of them is remounted too. Except the ones under the writable paths
+		let read_only_mounts = if read_only_root {
+			let writable_dirs: Vec<PathBuf> = writable_paths
+				.iter()
+				.map(|path| fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)))
+				.collect();
+			get_mounts()?
+				.into_iter()
+				.filter(|(mount_point, _)| {
+					!writable_dirs
+						.iter()
+						.any(|dir| Path::new(mount_point).starts_with(dir))
+				})
+				.map(|(mount_point, flags)| Ok((to_c_string(&mount_point)?, flags)))
+				.collect::<Result<Vec<_>, String>>()?
+		} else {
+			vec![]
+		};
+
+		let writable_paths = writable_paths
+			.iter()
+			.map(|path| to_c_string(path))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let root = sandbox.chroot.clone().unwrap_or_else(|| String::from("/"));
+		let chroot_working_dir = match Path::new(&working_dir).strip_prefix(&root) {
+			Ok(relative_dir) => Path::new("/").join(relative_dir),
+			Err(_) => Path::new("/").to_path_buf(),
+		};
+
+		let seccomp_filter = match sandbox.seccomp {
+			Some(preset) => Some(seccomp::get_filter(preset)?),
+			None => None,
+		};
+
+		Ok(Some(Sandbox {
+			namespaces,
+			read_only_root,
+			read_only_mounts,
+			writable_paths,
+			chroot: match &sandbox.chroot {
+				Some(chroot) => Some(to_c_string(chroot)?),
+				None => None,
+			},
+			working_dir: to_c_string(&chroot_working_dir.to_string_lossy())?,
+			proc_dir: to_c_string(&Path::new(&root).join("proc").to_string_lossy())?,
+			// seccomp can't be used by unprivileged processes without no_new_privs
+			no_new_privs: sandbox.no_new_privs.unwrap_or(false) || seccomp_filter.is_some(),
+			seccomp_filter,
+		}))
+	}
+
+	// Moves the current process into its namespaces and sets up its filesystem.
+	// This is called in the child process between fork and exec, while it
+	// still has the privileges to do so
+	pub fn enter(&self) -> io::Result<()> {
+		if self.namespaces.is_empty() {
+			return Ok(());
+		}
+		sched::unshare(self.namespaces).map_err(to_io_error)?;
+
+		if self.namespaces.contains(CloneFlags::CLONE_NEWNS) {
+			// Make sure none of the mounts here propagate back to the host
+			mount::mount::<str, str, str, str>(
+				None,
+				"/",
+				None,
+				MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+				None,
+			)
+			.map_err(to_io_error)?;
+		}
+
+		if self.read_only_root {
+			// Bind mounts are writable mounts of their own, so they stay
+			// writable after everything else is remounted as read-only
+			for path in self.writable_paths.iter() {
+				mount::mount::<_, _, str, str>(
+					Some(path.as_c_str()),
+					path.as_c_str(),
+					None,
+					MsFlags::MS_BIND | MsFlags::MS_REC,
+					None,
+				)
+				.map_err(to_io_error)?;
+			}
+			for (mount_point, flags) in self.read_only_mounts.iter() {
+				mount::mount::<str, _, str, str>(
+					None,
+					mount_point.as_c_str(),
+					None,
+					MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY | *flags,
+					None,
+				)
+				.map_err(to_io_error)?;
+			}
+		}
+
+		// A new PID namespace only applies to the children of the process that
+		// creates it. So fork once more, and let the parent stand in for the
+		// module until it exits
+		if self.namespaces.contains(CloneFlags::CLONE_NEWPID) {
+			match unistd::fork().map_err(to_io_error)? {
+				ForkResult::Parent { child } => wait_for_child(child),
+				ForkResult::Child => {
+					mount::mount::<str, _, str, str>(
+						Some("proc"),
+						self.proc_dir.as_c_str(),
+						Some("proc"),
+						MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+						None,
+					)
+					.map_err(to_io_error)?;
+				}
+			}
+		}
+
+		if let Some(chroot) = &self.chroot {
+			unistd::chroot(chroot.as_c_str()).map_err(to_io_error)?;
+		}
+		// The process is already in its working directory, but on the mount
+		// underneath the bind mount. Move over to the writable one
+		if self.read_only_root || self.chroot.is_some() {
+			unistd::chdir(self.working_dir.as_c_str()).map_err(to_io_error)?;
+		}
+
+		Ok(())
+	}
+
+	// Restricts what the process can do once it's been exec-ed.
+	// This is called after the process has dropped its privileges
+	pub fn restrict(&self) -> io::Result<()> {
+		if self.no_new_privs {
+			let result = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+			if result != 0 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+		if let Some(filter) = &self.seccomp_filter {
+			seccomp::apply_filter(filter)?;
+		}
+		Ok(())
+	}
+}
+
+// Makes sure the sandbox settings can be applied, without applying them
+#[cfg(target_os = "linux")]
+pub fn validate(config: &ModuleRunnerConfig) -> Result<(), String> {
+	Sandbox::new(config, "/", &None).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn validate(config: &ModuleRunnerConfig) -> Result<(), String> {
+	if config.sandbox.is_some() {
+		Err(String::from(
+			"Sandboxing modules is only supported on Linux",
+		))
+	} else {
+		Ok(())
+	}
+}
+
+// Waits for the sandboxed module to exit, and exits the same way it did
+#[cfg(target_os = "linux")]
+fn wait_for_child(child: unistd::Pid) -> ! {
+	unsafe {
+		// Signals meant for the module are sent to its whole process group,
+		// which the child is a part of. Leave them to the child
+		libc::signal(libc::SIGINT, libc::SIG_IGN);
+		libc::signal(libc::SIGTERM, libc::SIG_IGN);
+		libc::signal(libc::SIGHUP, libc::SIG_IGN);
+
+		// Don't hold on to any file descriptors (including the one std uses
+		// to find out if the exec succeeded) on behalf of the child
+		let mut limit = std::mem::zeroed::<libc::rlimit>();
+		let max_fd = if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) == 0 {
+			limit.rlim_cur.min(65536) as libc::c_int
+		} else {
+			1024
+		};
+		for fd in 3..max_fd {
+			libc::close(fd);
+		}
+
+		loop {
+			match wait::waitpid(child, None) {
+				Ok(WaitStatus::Exited(_, code)) => libc::_exit(code),
+				Ok(WaitStatus::Signaled(_, signal, _)) => {
+					libc::signal(signal as libc::c_int, libc::SIG_DFL);
+					libc::kill(libc::getpid(), signal as libc::c_int);
+					libc::_exit(128 + signal as libc::c_int);
+				}
+				Ok(_) => continue,
+				Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+				Err(_) => libc::_exit(1),
+			}
+		}
+	}
+}
+
+// Every mount point, with the flags that have to be kept when it's remounted.
+// Remounting a bind mount replaces its nosuid, nodev, noexec and atime flags
+#[cfg(target_os = "linux")]
+fn get_mounts() -> Result<Vec<(String, MsFlags)>, String> {
+	let mount_info = fs::read_to_string("/proc/self/mountinfo")
+		.map_err(|err| format!("Error reading /proc/self/mountinfo: {}", err))?;
+	Ok(mount_info
+		.lines()
+		.filter_map(|line| {
+			// Eg: 36 35 98:0 /mnt1 /mnt2 rw,nosuid,noatime master:1 - ext3 /dev/root rw
+			let mut fields = line.split(' ').skip(4);
+			let mount_point = unescape_mount_point(fields.next()?);
+			let mut flags = MsFlags::empty();
+			for option in fields.next()?.split(',') {
+				flags |= match option {
+					"nosuid" => MsFlags::MS_NOSUID,
+					"nodev" => MsFlags::MS_NODEV,
+					"noexec" => MsFlags::MS_NOEXEC,
+					"noatime" => MsFlags::MS_NOATIME,
+					"nodiratime" => MsFlags::MS_NODIRATIME,
+					"relatime" => MsFlags::MS_RELATIME,
+					_ => MsFlags::empty(),
+				};
+			}
+			Some((mount_point, flags))
+		})
+		.collect())
+}
+
+// Spaces, tabs, newlines and backslashes in mount points are escaped in octal. Eg: \040
+#[cfg(target_os = "linux")]
+fn unescape_mount_point(mount_point: &str) -> String {
+	let bytes = mount_point.as_bytes();
+	let mut unescaped = Vec::with_capacity(bytes.len());
+	let mut index = 0;
+	while index < bytes.len() {
+		let escaped = bytes.get(index + 1..index + 4).and_then(|digits| {
+			std::str::from_utf8(digits)
+				.ok()
+				.and_then(|digits| u8::from_str_radix(digits, 8).ok())
+		});
+		match escaped {
+			Some(byte) if bytes[index] == b'\\' => {
+				unescaped.push(byte);
+				index += 4;
+			}
+			_ => {
+				unescaped.push(bytes[index]);
+				index += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&unescaped).to_string()
+}
+
+#[cfg(target_os = "linux")]
+fn to_c_string(value: &str) -> Result<CString, String> {
+	CString::new(value).map_err(|_| format!("Invalid path: {}", value))
+}
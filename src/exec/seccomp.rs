@@ -0,0 +1,173 @@
+use crate::models::SeccompPreset;
+use std::io;
+
+// Mirrors of the kernel's BPF structs (linux/filter.h)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SockFilter {
+	code: u16,
+	jt: u8,
+	jf: u8,
+	k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+	len: libc::c_ushort,
+	filter: *const SockFilter,
+}
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+#[cfg(target_arch = "x86_64")]
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_JMP_JSET_K: u16 = 0x45;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+// Offsets into struct seccomp_data
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+// The lower half of the first argument. Both architectures are little endian
+const SECCOMP_DATA_ARG0: u32 = 16;
+
+// x32 syscalls have the same arch as x86_64 ones, with this bit set in the number
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// Not in libc yet. It's the same on both architectures
+const SYS_CLONE3: u32 = 435;
+
+const CLONE_NEW_NAMESPACE: u32 = (libc::CLONE_NEWNS
+	| libc::CLONE_NEWCGROUP
+	| libc::CLONE_NEWUTS
+	| libc::CLONE_NEWIPC
+	| libc::CLONE_NEWUSER
+	| libc::CLONE_NEWPID
+	| libc::CLONE_NEWNET) as u32;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn get_filter(preset: SeccompPreset) -> Result<Vec<SockFilter>, String> {
+	let blocked_syscalls = match preset {
+		SeccompPreset::Default => get_admin_syscalls(),
+	};
+
+	let mut filter = vec![
+		// Refuse to run if the syscall is made with a different ABI than the
+		// one the syscall numbers are for
+		statement(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+		jump(BPF_JMP_JEQ_K, AUDIT_ARCH, 1, 0),
+		statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32),
+		statement(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+	];
+	// Otherwise every syscall could still be made through its x32 number
+	#[cfg(target_arch = "x86_64")]
+	filter.extend(&[
+		jump(BPF_JMP_JGE_K, X32_SYSCALL_BIT, 0, 1),
+		statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32),
+	]);
+	for syscall in blocked_syscalls {
+		filter.push(jump(BPF_JMP_JEQ_K, syscall as u32, 0, 1));
+		filter.push(statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32));
+	}
+	filter.extend(&[
+		// clone3's flags are behind a pointer, which can't be checked. Refusing it with
+		// ENOSYS makes libc fall back to clone
+		jump(BPF_JMP_JEQ_K, SYS_CLONE3, 0, 1),
+		statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::ENOSYS as u32),
+		// Creating namespaces through clone is blocked just like through unshare
+		jump(BPF_JMP_JEQ_K, libc::SYS_clone as u32, 0, 3),
+		statement(BPF_LD_W_ABS, SECCOMP_DATA_ARG0),
+		jump(BPF_JMP_JSET_K, CLONE_NEW_NAMESPACE, 0, 1),
+		statement(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32),
+		statement(BPF_RET_K, SECCOMP_RET_ALLOW),
+	]);
+
+	Ok(filter)
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn get_filter(_: SeccompPreset) -> Result<Vec<SockFilter>, String> {
+	Err(String::from(
+		"seccomp presets are not supported on this architecture",
+	))
+}
+
+// Loads the filter into the current process. This needs no_new_privs to be
+// set, unless the process is privileged
+pub fn apply_filter(filter: &[SockFilter]) -> io::Result<()> {
+	let program = SockFprog {
+		len: filter.len() as libc::c_ushort,
+		filter: filter.as_ptr(),
+	};
+	let result = unsafe {
+		libc::prctl(
+			libc::PR_SET_SECCOMP,
+			libc::SECCOMP_MODE_FILTER,
+			&program as *const SockFprog,
+		)
+	};
+	if result != 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+fn statement(code: u16, k: u32) -> SockFilter {
+	SockFilter {
+		code,
+		jt: 0,
+		jf: 0,
+		k,
+	}
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+	SockFilter { code, jt, jf, k }
+}
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn get_admin_syscalls() -> Vec<libc::c_long> {
+	let mut syscalls = vec![
+		libc::SYS_mount,
+		libc::SYS_umount2,
+		libc::SYS_pivot_root,
+		libc::SYS_swapon,
+		libc::SYS_swapoff,
+		libc::SYS_reboot,
+		libc::SYS_kexec_load,
+		libc::SYS_init_module,
+		libc::SYS_finit_module,
+		libc::SYS_delete_module,
+		libc::SYS_ptrace,
+		libc::SYS_process_vm_readv,
+		libc::SYS_process_vm_writev,
+		libc::SYS_bpf,
+		libc::SYS_perf_event_open,
+		libc::SYS_setns,
+		libc::SYS_unshare,
+		libc::SYS_keyctl,
+		libc::SYS_add_key,
+		libc::SYS_request_key,
+		libc::SYS_acct,
+		libc::SYS_quotactl,
+		libc::SYS_settimeofday,
+		libc::SYS_clock_settime,
+		libc::SYS_clock_adjtime,
+		libc::SYS_adjtimex,
+		libc::SYS_open_by_handle_at,
+		libc::SYS_userfaultfd,
+		libc::SYS_syslog,
+	];
+	#[cfg(target_arch = "x86_64")]
+	syscalls.extend(&[libc::SYS_iopl, libc::SYS_ioperm, libc::SYS_kexec_file_load]);
+
+	syscalls
+}
@@ -0,0 +1,50 @@
+use crate::utils::{logger, procfs};
+use nix::{
+	sys::{
+		signal::{self, Signal},
+		wait::{self, WaitPidFlag},
+	},
+	unistd::{self, Pid},
+};
+
+// Mark guillotine as a child subreaper, so that any descendants orphaned by
+// the modules get re-parented to guillotine rather than to init
+pub fn enable() {
+	let result = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+	if result != 0 {
+		logger::error(&format!(
+			"Error registering as a child subreaper: {}",
+			std::io::Error::last_os_error()
+		));
+	}
+}
+
+// Reap orphaned descendants that have exited. Processes that are tracked by a
+// ProcessRunner are left alone, so that their exit status isn't lost
+pub fn reap_orphans(tracked_pids: &[u32]) {
+	for pid in get_orphans(tracked_pids) {
+		if pid.state == 'Z' {
+			wait::waitpid(Pid::from_raw(pid.pid), Some(WaitPidFlag::WNOHANG)).ok();
+		}
+	}
+}
+
+// Kill and reap all the orphaned descendants that are still around
+pub fn kill_orphans(tracked_pids: &[u32]) {
+	for pid in get_orphans(tracked_pids) {
+		if pid.state != 'Z' {
+			logger::info(&format!("Killing orphaned process: {}", pid.pid));
+			signal::kill(Pid::from_raw(pid.pid), Signal::SIGKILL).ok();
+		}
+		wait::waitpid(Pid::from_raw(pid.pid), None).ok();
+	}
+}
+
+fn get_orphans(tracked_pids: &[u32]) -> Vec<procfs::ProcessStat> {
+	let own_pid = unistd::getpid().as_raw();
+	procfs::list_processes()
+		.into_iter()
+		.filter(|process| process.ppid == own_pid)
+		.filter(|process| !tracked_pids.contains(&(process.pid as u32)))
+		.collect()
+}
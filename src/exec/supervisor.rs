@@ -0,0 +1,245 @@
+use crate::{
+	exec::runner,
+	models::{Event, GuillotineMessage, GuillotineSpecificConfig, ProcessInfo},
+};
+
+use async_std::task;
+use futures::channel::{
+	mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+	oneshot::{channel, Sender},
+};
+use std::{
+	sync::{Arc, Mutex},
+	thread,
+};
+
+// How many supervisors can be running in a process at a time
+pub(crate) const MAX_SUPERVISORS: usize = 8;
+
+// Juno's function handlers are plain functions, so they can't hold on to the supervisor
+// they're for. Each running supervisor claims a slot here, and the handlers declared for
+// that slot find its sender in it. Whether it's running with the subreaper is kept too
+lazy_static! {
+	static ref SLOTS: Mutex<[Option<(UnboundedSender<GuillotineMessage>, bool)>; MAX_SUPERVISORS]> =
+		Mutex::new(Default::default());
+}
+
+// Runs Juno and the modules in a config, and keeps them running. Clones control the same
+// modules, so one can be kept around to stop them while another is running them.
+// With the subreaper option, the whole process becomes a child subreaper, and any child
+// process of its own that isn't a module is reaped by the supervisor as soon as it exits.
+// So don't use it if something else waits on them. That includes other supervisors, so
+// it can only be used by a supervisor that's running on its own in the process
+#[derive(Clone)]
+pub struct Supervisor {
+	config: GuillotineSpecificConfig,
+	watch_all: bool,
+	sender: UnboundedSender<GuillotineMessage>,
+	// Taken by the supervision loop when it's started
+	receiver: Arc<Mutex<Option<UnboundedReceiver<GuillotineMessage>>>>,
+}
+
+impl Supervisor {
+	pub fn new(config: GuillotineSpecificConfig) -> Self {
+		let (sender, receiver) = unbounded::<GuillotineMessage>();
+		Supervisor {
+			config,
+			watch_all: false,
+			sender,
+			receiver: Arc::new(Mutex::new(Some(receiver))),
+		}
+	}
+
+	// Restarts every module when its files change, whether or not it's configured to
+	pub fn watch_all(mut self, watch_all: bool) -> Self {
+		self.watch_all = watch_all;
+		self
+	}
+
+	// Runs the modules till the supervisor is stopped. Fails if Juno couldn't be started
+	pub async fn run(&self) -> Result<(), String> {
+		let running = self.claim()?;
+		let receiver = self.take_receiver()?;
+		let (sender, started) = channel::<Result<(), String>>();
+		let stopped = runner::run(
+			self.config.clone(),
+			self.watch_all,
+			running.0,
+			receiver,
+			sender,
+		)
+		.await;
+		running.release(stopped);
+		started.await.unwrap_or(Ok(()))
+	}
+
+	// Runs the modules in the background. Returns once Juno is up and the supervisor
+	// has started on the modules, or fails if Juno couldn't be started
+	pub async fn start(&self) -> Result<(), String> {
+		let running = self.claim()?;
+		let receiver = self.take_receiver()?;
+		let config = self.config.clone();
+		let watch_all = self.watch_all;
+		let (started_sender, started) = channel::<Result<(), String>>();
+		let thread = thread::spawn(move || {
+			let stopped = task::block_on(runner::run(
+				config,
+				watch_all,
+				running.0,
+				receiver,
+				started_sender,
+			));
+			running.release(stopped);
+		});
+		match started.await {
+			Ok(Ok(())) => Ok(()),
+			result => {
+				// It's on its way out. Once it's gone, its slot is free again
+				thread.join().ok();
+				// If the supervisor panicked before it was up, the result was never sent
+				result
+					.unwrap_or_else(|_| Err(String::from("The supervisor stopped while starting")))
+			}
+		}
+	}
+
+	// Stops every module, and then Juno. Returns once they've all stopped
+	pub async fn stop(&self) {
+		// If it hasn't been started, there's nothing to stop
+		if self.receiver.lock().unwrap().is_some() {
+			return;
+		}
+		let (sender, receiver) = channel::<()>();
+		// If the supervisor isn't running anymore, there's nothing to wait for
+		if self
+			.sender
+			.unbounded_send(GuillotineMessage::Shutdown(sender))
+			.is_ok()
+		{
+			receiver.await.ok();
+		}
+	}
+
+	// Restarts the process with the given module ID. 0 is Juno
+	pub async fn restart(&self, module_id: u64) -> Result<(), String> {
+		let (sender, receiver) = channel::<bool>();
+		self.send(GuillotineMessage::RestartProcess(module_id, sender))?;
+		match receiver.await {
+			Ok(true) => Ok(()),
+			Ok(false) => Err(format!("There's no process with the ID {}", module_id)),
+			Err(_) => Err(String::from("The supervisor has stopped")),
+		}
+	}
+
+	// Changes the number of instances of a module run in cluster mode
+	pub async fn scale(&self, name: &str, instances: usize) -> Result<(), String> {
+		let (sender, receiver) = channel::<Result<(), String>>();
+		self.send(GuillotineMessage::ScaleProcess(
+			name.to_string(),
+			instances,
+			sender,
+		))?;
+		receiver
+			.await
+			.unwrap_or_else(|_| Err(String::from("The supervisor has stopped")))
+	}
+
+	// Replaces every instance of a module, one at a time. Returns how many there are
+	pub async fn reload(&self, name: &str) -> Result<usize, String> {
+		let (sender, receiver) = channel::<Result<usize, String>>();
+		self.send(GuillotineMessage::ReloadProcess(name.to_string(), sender))?;
+		receiver
+			.await
+			.unwrap_or_else(|_| Err(String::from("The supervisor has stopped")))
+	}
+
+	// Juno, followed by every module's processes
+	pub async fn list(&self) -> Result<Vec<ProcessInfo>, String> {
+		let (sender, receiver) = channel::<Vec<ProcessInfo>>();
+		self.send(GuillotineMessage::ListProcesses(sender))?;
+		receiver
+			.await
+			.map_err(|_| String::from("The supervisor has stopped"))
+	}
+
+	// Every event from here on. Subscribing before it's started gets the first ones too.
+	// The stream ends when the supervisor stops
+	pub fn subscribe(&self) -> UnboundedReceiver<Event> {
+		let (sender, receiver) = unbounded::<Event>();
+		self.sender
+			.unbounded_send(GuillotineMessage::Subscribe(sender))
+			.ok();
+		receiver
+	}
+
+	// Anything sent before it's started would only be answered once it is
+	fn send(&self, message: GuillotineMessage) -> Result<(), String> {
+		if self.receiver.lock().unwrap().is_some() {
+			return Err(String::from("The supervisor hasn't been started"));
+		}
+		self.sender
+			.unbounded_send(message)
+			.map_err(|_| String::from("The supervisor has stopped"))
+	}
+
+	// The subreaper reaps the process's child processes that aren't its own modules, so
+	// a supervisor using it can't share the process with another one
+	fn claim(&self) -> Result<Running, String> {
+		let subreaper = cfg!(target_os = "linux") && self.config.subreaper.unwrap_or(false);
+		let mut slots = SLOTS.lock().unwrap();
+		let running = slots.iter().flatten().collect::<Vec<_>>();
+		if subreaper && !running.is_empty() {
+			return Err(String::from(
+				"A supervisor with the subreaper can't run alongside another one",
+			));
+		}
+		if running.iter().any(|(_, subreaper)| *subreaper) {
+			return Err(String::from(
+				"A supervisor with the subreaper is already running in this process",
+			));
+		}
+		let index = slots.iter().position(Option::is_none).ok_or_else(|| {
+			format!(
+				"There are already {} supervisors running in this process",
+				MAX_SUPERVISORS
+			)
+		})?;
+		slots[index] = Some((self.sender.clone(), subreaper));
+		Ok(Running(index))
+	}
+
+	fn take_receiver(&self) -> Result<UnboundedReceiver<GuillotineMessage>, String> {
+		self.receiver
+			.lock()
+			.unwrap()
+			.take()
+			.ok_or_else(|| String::from("The supervisor has already been started"))
+	}
+}
+
+// The sender of the supervisor running in the slot. Eg: for Juno's function handlers
+pub(crate) fn get_sender(slot: usize) -> Option<UnboundedSender<GuillotineMessage>> {
+	SLOTS.lock().unwrap()[slot]
+		.as_ref()
+		.map(|(sender, _)| sender.clone())
+}
+
+// The slot a running supervisor holds, till it's dropped. That includes panicking, so
+// that the slot is freed however the supervisor stops
+struct Running(usize);
+
+impl Running {
+	// Lets whatever stopped the supervisor know that it has, once its slot is free
+	fn release(self, stopped: Option<Sender<()>>) {
+		drop(self);
+		if let Some(stopped) = stopped {
+			stopped.send(()).ok();
+		}
+	}
+}
+
+impl Drop for Running {
+	fn drop(&mut self) {
+		SLOTS.lock().unwrap()[self.0] = None;
+	}
+}
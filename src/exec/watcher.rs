@@ -0,0 +1,194 @@
+use crate::models::WatchConfig;
+use glob::Pattern;
+use nix::{
+	errno::Errno,
+	sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+	unistd,
+};
+use std::{
+	collections::HashMap,
+	fs,
+	os::unix::io::AsRawFd,
+	path::{Path, PathBuf},
+	time::{Duration, Instant},
+};
+
+// What's ignored when the ignore patterns aren't set
+const DEFAULT_IGNORE: [&str; 2] = [".git", "node_modules"];
+const DEFAULT_DEBOUNCE: u64 = 500;
+
+// Watches a module's files for changes, using inotify. Directories are
+// watched recursively
+#[derive(Debug)]
+pub struct Watcher {
+	inotify: Inotify,
+	// The path each watch is for
+	watches: HashMap<WatchDescriptor, PathBuf>,
+	root: PathBuf,
+	ignore: Vec<Pattern>,
+	// Paths that are never watched, like the module's log directory
+	excluded: Vec<PathBuf>,
+	debounce: Duration,
+	// When the last change was seen, if it hasn't been acted on yet
+	changed_at: Option<Instant>,
+}
+
+impl Watcher {
+	pub fn new(
+		config: &WatchConfig,
+		working_dir: &str,
+		log_dir: &Option<String>,
+	) -> Result<Self, String> {
+		let ignore = get_ignore_patterns(config)?;
+		let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+			.map_err(|err| format!("Error initializing inotify: {}", err))?;
+		let root = fs::canonicalize(working_dir).unwrap_or_else(|_| PathBuf::from(working_dir));
+
+		let mut watcher = Watcher {
+			inotify,
+			watches: HashMap::new(),
+			root: root.clone(),
+			ignore,
+			// The module would be restarted every time it logs something
+			excluded: log_dir
+				.iter()
+				.filter_map(|log_dir| fs::canonicalize(log_dir).ok())
+				.collect(),
+			debounce: Duration::from_millis(config.debounce.unwrap_or(DEFAULT_DEBOUNCE)),
+			changed_at: None,
+		};
+		let paths = config
+			.paths
+			.clone()
+			.unwrap_or_else(|| vec![String::from(".")]);
+		for path in paths {
+			let path = root.join(&path);
+			let path = fs::canonicalize(&path)
+				.map_err(|err| format!("Error watching '{}': {}", path.to_string_lossy(), err))?;
+			watcher.watch(&path)?;
+		}
+		Ok(watcher)
+	}
+
+	// Checks for changes, without blocking. Returns true once there's been
+	// a change, and nothing else has changed for the debounce duration
+	pub fn poll(&mut self) -> bool {
+		while let Ok(events) = self.inotify.read_events() {
+			for event in events {
+				if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+					self.watches.remove(&event.wd);
+					continue;
+				}
+				let path = match (self.watches.get(&event.wd), &event.name) {
+					(Some(path), Some(name)) => path.join(name),
+					(Some(path), None) => path.clone(),
+					(None, _) => continue,
+				};
+				if self.is_ignored(&path) {
+					continue;
+				}
+				if event.mask.contains(AddWatchFlags::IN_ISDIR)
+					&& event
+						.mask
+						.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+				{
+					// Not being able to watch a new directory isn't worth stopping for
+					let _ = self.watch(&path);
+				}
+				self.changed_at = Some(Instant::now());
+			}
+		}
+
+		match self.changed_at {
+			Some(changed_at) if changed_at.elapsed() >= self.debounce => {
+				self.changed_at = None;
+				true
+			}
+			_ => false,
+		}
+	}
+
+	fn watch(&mut self, path: &Path) -> Result<(), String> {
+		if self.is_ignored(path) {
+			return Ok(());
+		}
+		let watch = self
+			.inotify
+			.add_watch(
+				path,
+				AddWatchFlags::IN_CREATE
+					| AddWatchFlags::IN_DELETE
+					| AddWatchFlags::IN_CLOSE_WRITE
+					| AddWatchFlags::IN_MOVED_FROM
+					| AddWatchFlags::IN_MOVED_TO,
+			)
+			.map_err(|err| match err {
+				nix::Error::Sys(Errno::ENOSPC) => format!(
+					"Too many files to watch in '{}'. Ignore some, or raise fs.inotify.max_user_watches",
+					self.root.to_string_lossy()
+				),
+				err => format!("Error watching '{}': {}", path.to_string_lossy(), err),
+			})?;
+		self.watches.insert(watch, path.to_path_buf());
+
+		// Symlinks aren't followed, so that they can't lead anywhere already watched
+		let entries = match fs::read_dir(path) {
+			Ok(entries) => entries,
+			Err(_) => return Ok(()),
+		};
+		for entry in entries.filter_map(Result::ok) {
+			if entry
+				.file_type()
+				.map(|file_type| file_type.is_dir())
+				.unwrap_or(false)
+			{
+				self.watch(&entry.path())?;
+			}
+		}
+		Ok(())
+	}
+
+	// Patterns match either the path relative to the working directory, or
+	// the name of any directory in it
+	fn is_ignored(&self, path: &Path) -> bool {
+		if self
+			.excluded
+			.iter()
+			.any(|excluded| path.starts_with(excluded))
+		{
+			return true;
+		}
+		let relative_path = match path.strip_prefix(&self.root) {
+			Ok(relative_path) => relative_path,
+			Err(_) => path,
+		};
+		self.ignore.iter().any(|pattern| {
+			pattern.matches_path(relative_path)
+				|| relative_path
+					.iter()
+					.any(|component| pattern.matches(&component.to_string_lossy()))
+		})
+	}
+}
+
+impl Drop for Watcher {
+	fn drop(&mut self) {
+		let _ = unistd::close(self.inotify.as_raw_fd());
+	}
+}
+
+pub fn get_ignore_patterns(config: &WatchConfig) -> Result<Vec<Pattern>, String> {
+	match &config.ignore {
+		Some(ignore) => ignore
+			.iter()
+			.map(|pattern| {
+				Pattern::new(pattern)
+					.map_err(|err| format!("Invalid pattern '{}': {}", pattern, err))
+			})
+			.collect(),
+		None => Ok(DEFAULT_IGNORE
+			.iter()
+			.map(|pattern| Pattern::new(pattern).unwrap())
+			.collect()),
+	}
+}
@@ -0,0 +1,33 @@
+#[macro_use]
+extern crate lazy_static;
+extern crate async_std;
+extern crate chrono;
+extern crate colored;
+extern crate futures;
+extern crate futures_timer;
+extern crate glob;
+extern crate juno;
+extern crate schemars;
+extern crate serde;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+
+#[cfg(target_family = "unix")]
+extern crate libc;
+#[cfg(target_family = "unix")]
+extern crate nix;
+
+#[cfg(target_family = "windows")]
+extern crate winapi;
+
+mod exec;
+pub mod models;
+pub(crate) mod utils;
+
+pub use exec::{
+	runner::{check_dependencies, check_module_config},
+	supervisor::Supervisor,
+};
+pub use utils::{constants, logger, secrets::uses_store as uses_secrets_store};
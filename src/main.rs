@@ -1,38 +1,22 @@
-#[cfg(feature = "serde_derive")]
-#[macro_use]
-extern crate serde_derive;
 #[macro_use]
 extern crate lazy_static;
 extern crate async_std;
 extern crate chrono;
 extern crate clap;
 extern crate cli_table;
-extern crate colored;
 extern crate ctrlc;
 extern crate futures;
-extern crate futures_timer;
+extern crate guillotine;
 extern crate juno;
-extern crate serde;
 extern crate serde_json;
 
-#[cfg(target_family = "unix")]
-extern crate nix;
-
-#[cfg(target_family = "windows")]
-extern crate winapi;
-
 mod cli;
-mod exec;
-mod models;
-mod utils;
 
-use exec::runner;
-use models::parser;
-use utils::{constants, logger};
+use guillotine::{constants, logger, models};
+use models::{parser, EVENT_HOOKS};
 
 use async_std::{fs, path::Path, task};
 use clap::{App, Arg, SubCommand};
-use futures::future;
 
 #[async_std::main]
 async fn main() {
@@ -41,7 +25,13 @@ async fn main() {
 		.author(constants::APP_AUTHORS)
 		.about(constants::APP_ABOUT)
 		.subcommand(
-			SubCommand::with_name("run").about("Run the application with a given config file"),
+			SubCommand::with_name("run")
+				.about("Run the application with a given config file")
+				.arg(
+					Arg::with_name("watch")
+						.long("watch")
+						.help("Restarts every module when its files change"),
+				),
 		)
 		.subcommand(
 			SubCommand::with_name("list-processes")
@@ -73,6 +63,78 @@ async fn main() {
 						.allow_hyphen_values(false),
 				),
 		)
+		.subcommand(
+			SubCommand::with_name("scale")
+				.about("Changes the number of instances of a module run in cluster mode")
+				.arg(
+					Arg::with_name("name")
+						.takes_value(true)
+						.required(true)
+						.allow_hyphen_values(false),
+				)
+				.arg(
+					Arg::with_name("instances")
+						.takes_value(true)
+						.required(true)
+						.allow_hyphen_values(false),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name("reload")
+				.about("Replaces every instance of a module, starting each replacement before stopping the old one")
+				.arg(
+					Arg::with_name("name")
+						.takes_value(true)
+						.required(true)
+						.allow_hyphen_values(false),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name("events")
+				.about("Prints what happens to the modules (starts, exits, restarts, reloads) as it happens")
+				.arg(
+					Arg::with_name("module")
+						.long("module")
+						.short("m")
+						.takes_value(true)
+						.multiple(true)
+						.number_of_values(1)
+						.help("Only prints the events of this module"),
+				)
+				.arg(
+					Arg::with_name("type")
+						.long("type")
+						.short("t")
+						.takes_value(true)
+						.multiple(true)
+						.number_of_values(1)
+						.possible_values(&EVENT_HOOKS)
+						.help("Only prints events of this type"),
+				)
+				.arg(
+					Arg::with_name("json")
+						.long("json")
+						.help("Prints each event as a line of JSON"),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name("validate")
+				.about("Checks the config file and every module's manifest for problems"),
+		)
+		.subcommand(
+			SubCommand::with_name("schema")
+				.about("Prints the JSON Schema for the config file or a module's manifest")
+				.arg(
+					Arg::with_name("kind")
+						.takes_value(true)
+						.possible_values(&["config", "module"])
+						.default_value("config"),
+				),
+		)
+		.subcommand(
+			SubCommand::with_name("migrate-config")
+				.about("Upgrades the config file to the current version, backing up the old one"),
+		)
 		.arg(
 			Arg::with_name("config")
 				.short("c")
@@ -81,12 +143,25 @@ async fn main() {
 				.value_name("FILE")
 				.default_value("./config.json")
 				.multiple(false)
-				.help("Sets the location of the config file"),
+				.help("Sets the location of the config file (JSON, YAML or TOML)"),
+		)
+		.arg(
+			Arg::with_name("profile")
+				.long("profile")
+				.takes_value(true)
+				.value_name("NAME")
+				.multiple(false)
+				.help("Selects the configs with this profile"),
 		)
 		.get_matches();
 
 	ctrlc::set_handler(|| task::block_on(on_exit())).expect("Error setting the CtrlC handler");
 
+	if let ("schema", Some(args)) = args.subcommand() {
+		cli::print_schema(args);
+		return;
+	}
+
 	let config_path = Path::new(args.value_of("config").unwrap_or("./config.json"));
 
 	if !config_path.exists().await {
@@ -101,24 +176,41 @@ async fn main() {
 		println!("Error reading config file: {}", err);
 		return;
 	}
-	let config_result = parser::select_config(file_contents.unwrap()).await;
-	if let Err(err) = config_result {
-		println!("Error selecting a configuration to run: {}", err);
+	let file_contents = file_contents.unwrap();
+	if let ("validate", Some(_)) = args.subcommand() {
+		cli::validate(config_path, file_contents, args.value_of("profile")).await;
+		return;
+	}
+	if let ("migrate-config", Some(_)) = args.subcommand() {
+		cli::migrate_config(config_path, file_contents).await;
+		return;
+	}
+
+	let config_result =
+		parser::select_config(config_path, file_contents, args.value_of("profile")).await;
+	if let Err(errors) = config_result {
+		println!("Error selecting a configuration to run:");
+		for err in errors {
+			println!("  {}", err.in_file(&config_path.to_string_lossy()));
+		}
 		return;
 	}
 	let config = config_result.unwrap();
 
 	match args.subcommand() {
-		("run", Some(_)) => runner::run(config).await,
+		("run", Some(args)) => cli::run(config, args).await,
 		("list-processes", Some(_)) => cli::list_processes(config).await,
 		("list-modules", Some(_)) => cli::list_modules(config).await,
 		("info", Some(args)) => cli::get_module_info(config, args).await,
 		("restart", Some(args)) => cli::restart_process(config, args).await,
+		("scale", Some(args)) => cli::scale_process(config, args).await,
+		("reload", Some(args)) => cli::reload_process(config, args).await,
+		("events", Some(args)) => cli::watch_events(config, args).await,
 		(cmd, _) => println!("Unknown command '{}'", cmd),
 	}
 }
 
 async fn on_exit() {
 	logger::info("Recieved exit code. Closing all modules");
-	future::join(runner::on_exit(), cli::on_exit()).await;
+	cli::on_exit().await;
 }
@@ -1,12 +1,22 @@
-use crate::exec::process::ProcessRunner;
-use futures::channel::oneshot::Sender;
+use crate::models::{Event, ProcessInfo};
+use futures::channel::{mpsc::UnboundedSender, oneshot::Sender};
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum GuillotineMessage {
 	ListModules(Sender<Vec<String>>),
-	ListProcesses(Sender<Vec<ProcessRunner>>),
+	ListProcesses(Sender<Vec<ProcessInfo>>),
 	RestartProcess(u64, Sender<bool>),
+	// The name of a module run in cluster mode, and how many instances it should have
+	ScaleProcess(String, usize, Sender<Result<(), String>>),
+	// Reloads every instance of a module, one at a time. Responds with how many there are
+	ReloadProcess(String, Sender<Result<usize, String>>),
+	// The latest events, after the one with the given ID
+	ListEvents(Option<u64>, Sender<Vec<Event>>),
+	// Sends every event from here on to the subscriber
+	Subscribe(UnboundedSender<Event>),
+	// Stops every module, and then Juno. Responds once they've all stopped
+	Shutdown(Sender<()>),
 	StopProcess,
 	StartProcess,
 	DeleteProcess,
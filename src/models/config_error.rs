@@ -0,0 +1,70 @@
+use std::fmt;
+
+// A problem with a config file or module manifest, and where it is
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+	pub file: Option<String>,
+	// The path to the field with the problem. Eg: configs[1].config.juno.path
+	pub field: Option<String>,
+	pub line: Option<usize>,
+	pub column: Option<usize>,
+	pub message: String,
+}
+
+impl ConfigError {
+	pub fn new(field: &str, message: &str) -> Self {
+		ConfigError {
+			file: None,
+			field: if field.is_empty() {
+				None
+			} else {
+				Some(field.to_string())
+			},
+			line: None,
+			column: None,
+			message: message.to_string(),
+		}
+	}
+
+	// Errors in included configs already know which file they're in
+	pub fn in_file(mut self, file: &str) -> Self {
+		if self.file.is_none() {
+			self.file = Some(file.to_string());
+		}
+		self
+	}
+
+	// Adds the prefix to the field path. Eg: config + juno.path
+	pub fn with_prefix(mut self, prefix: &str) -> Self {
+		self.field = Some(match self.field {
+			Some(field) if field.starts_with('[') => format!("{}{}", prefix, field),
+			Some(field) => format!("{}.{}", prefix, field),
+			None => prefix.to_string(),
+		});
+		self
+	}
+
+	pub fn at(mut self, line: usize, column: usize) -> Self {
+		self.line = Some(line);
+		self.column = Some(column);
+		self
+	}
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(file) = &self.file {
+			write!(f, "{}", file)?;
+			if let (Some(line), Some(column)) = (self.line, self.column) {
+				write!(f, ":{}:{}", line, column)?;
+			}
+			write!(f, ": ")?;
+		} else if let (Some(line), Some(column)) = (self.line, self.column) {
+			write!(f, "line {} column {}: ", line, column)?;
+		}
+		if let Some(field) = &self.field {
+			write!(f, "{}: ", field)?;
+		}
+		write!(f, "{}", self.message)
+	}
+}
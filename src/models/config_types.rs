@@ -1,34 +1,148 @@
+use schemars::{
+	gen::SchemaGenerator,
+	schema::{InstanceType, ObjectValidation, Schema, SchemaObject, SubschemaValidation},
+	JsonSchema,
+};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
 use serde_derive::Deserialize;
+use serde_json::Value;
+use std::{collections::HashMap, fmt};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct GuillotineConfig {
 	pub version: String,
+	// Other config files to merge, in order, before this one. Relative to this file
+	pub include: Option<Vec<String>>,
 	pub configs: Option<Vec<GuillotinePerEnvConfig>>,
-	pub config: Option<GuillotineSpecificConfig>,
+	// The base config. The config that matches the environment is merged into this
+	#[schemars(with = "Option<GuillotineSpecificConfig>")]
+	pub config: Option<Value>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct GuillotinePerEnvConfig {
 	pub env: EnvRequirements,
-	pub config: GuillotineSpecificConfig,
+	// A GuillotineSpecificConfig, which only needs to be complete once it's merged with the base config
+	#[schemars(schema_with = "get_partial_config_schema")]
+	pub config: Value,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 pub struct EnvRequirements {
 	pub target_family: Option<String>,
 	pub target_os: Option<String>,
 	pub target_arch: Option<String>,
 	pub target_endian: Option<String>,
+	// A glob pattern. Eg: web-*
+	pub hostname: Option<String>,
+	pub env_vars: Option<HashMap<String, String>>,
+	pub file_exists: Option<String>,
+	// Only matches when guillotine is run with --profile <profile>
+	pub profile: Option<String>,
 }
 
 // Config specific to this environment
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, JsonSchema, Clone)]
 pub struct GuillotineSpecificConfig {
 	pub juno: JunoConfig,
 	pub modules: Option<GuillotineModuleConfig>,
+	// Processes declared in the config itself, rather than in a module's directory
+	pub processes: Option<Vec<ModuleRunnerConfig>>,
+	pub subreaper: Option<bool>,
+	// Environment variables set for every module
+	pub env: Option<HashMap<String, String>>,
+	// Set to false to not pass the Juno connection details and module info to modules
+	pub inject_env: Option<bool>,
+	// Fields to change in a module's manifest, keyed by the module's name
+	#[schemars(schema_with = "get_overrides_schema")]
+	pub overrides: Option<HashMap<String, Value>>,
+	pub secrets_store: Option<SecretsStoreConfig>,
+	pub notifications: Option<NotificationsConfig>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct NotificationsConfig {
+	pub targets: Vec<NotificationTarget>,
+	pub crash_loop: Option<CrashLoopConfig>,
+}
+
+// Where notifications are sent. Either a webhook, or a command
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct NotificationTarget {
+	// An http(s) URL the event is POSTed to as JSON. It's sent with curl, which has to be on the PATH
+	pub webhook: Option<String>,
+	// Extra headers sent with the webhook. Eg: Authorization
+	pub headers: Option<HashMap<String, String>>,
+	// Run with sh -c, with the event in the GUILLOTINE_EVENT* environment variables
+	pub command: Option<String>,
+	// The events to notify about. Defaults to crashed and crash_loop
+	pub events: Option<Vec<NotificationEvent>>,
+	// Only notify about these modules
+	pub modules: Option<Vec<String>>,
+	pub rate_limit: Option<RateLimitConfig>,
+	// How long (in ms) sending a notification can take. Defaults to 10000
+	pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+	Started,
+	Restarted,
+	// Exited successfully, or was stopped
+	Exited,
+	Crashed,
+	// Couldn't be started
+	Errored,
+	Reloaded,
+	CrashLoop,
+	JunoRestarted,
+}
+
+impl fmt::Display for NotificationEvent {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NotificationEvent::Started => write!(f, "started"),
+			NotificationEvent::Restarted => write!(f, "restarted"),
+			NotificationEvent::Exited => write!(f, "exited"),
+			NotificationEvent::Crashed => write!(f, "crashed"),
+			NotificationEvent::Errored => write!(f, "errored"),
+			NotificationEvent::Reloaded => write!(f, "reloaded"),
+			NotificationEvent::CrashLoop => write!(f, "crash_loop"),
+			NotificationEvent::JunoRestarted => write!(f, "juno_restarted"),
+		}
+	}
+}
+
+// At most `count` notifications about a module are sent in each period.
+// The rest are counted, and the count is sent with the next notification
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct RateLimitConfig {
+	// Defaults to 5
+	pub count: Option<usize>,
+	// In ms. Defaults to 300000 (5 minutes)
+	pub period: Option<u64>,
+}
+
+// A module that crashes this many times within the period is in a crash loop
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct CrashLoopConfig {
+	// Defaults to 5
+	pub crashes: Option<usize>,
+	// In ms. Defaults to 60000
+	pub period: Option<u64>,
+}
+
+// An encrypted file of secrets. It's decrypted with the decrypt_command,
+// which has to print a JSON object of secret names to values
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct SecretsStoreConfig {
+	pub path: String,
+	// The path is passed as the last argument. Defaults to gpg
+	pub decrypt_command: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, JsonSchema, Clone)]
 pub struct JunoConfig {
 	pub path: String,
 	pub connection_type: String,
@@ -37,25 +151,420 @@ pub struct JunoConfig {
 	pub socket_path: Option<String>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, JsonSchema, Clone)]
 pub struct GuillotineModuleConfig {
-	pub path: String,
+	// Directories containing modules, or glob patterns of them
+	pub path: Option<String>,
+	pub paths: Option<Vec<String>>,
 	pub logs: Option<String>,
+	// Look for modules in sub-directories of directories that aren't modules,
+	// up to max_depth levels deep
+	pub recursive: Option<bool>,
+	pub max_depth: Option<usize>,
+	// Glob patterns of module directories, relative to the module path
+	pub include: Option<Vec<String>>,
+	pub exclude: Option<Vec<String>>,
 }
 
-#[derive(Debug, Clone)]
+impl GuillotineModuleConfig {
+	// Gets all the module paths, along with their field paths
+	pub fn get_paths(&self) -> Vec<(String, String)> {
+		let mut paths = vec![];
+		if let Some(path) = &self.path {
+			paths.push((String::from("modules.path"), path.clone()));
+		}
+		for (index, path) in self.paths.iter().flatten().enumerate() {
+			paths.push((format!("modules.paths[{}]", index), path.clone()));
+		}
+		paths
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum ModuleRunningStatus {
 	Running,
 	Offline,
+	// Waiting for the modules it depends on
+	Waiting,
+	// A oneshot that exited successfully, or didn't
+	Completed,
+	Failed,
+}
+
+#[derive(Debug, Clone)]
+pub enum CrashReason {
+	ExitCode(i32),
+	Signal(i32),
+	OutOfMemory,
+	// The pre_start hook failed, so it wasn't started at all
+	PreStartFailed,
+}
+
+impl fmt::Display for CrashReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CrashReason::ExitCode(code) => write!(f, "exited with code {}", code),
+			CrashReason::Signal(signal) => write!(f, "killed by signal {}", signal),
+			CrashReason::OutOfMemory => write!(f, "killed for running out of memory"),
+			CrashReason::PreStartFailed => write!(f, "its pre_start hook failed"),
+		}
+	}
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
 pub struct ModuleRunnerConfig {
 	pub name: String,
+	pub enabled: Option<bool>,
 	pub command: String,
 	pub interpreter: Option<String>,
 	pub args: Option<Vec<String>>,
+	// The directory to run the process in. Relative to the module's directory,
+	// and required for processes declared in the main config
+	pub cwd: Option<String>,
+	pub env: Option<HashMap<String, String>>,
+	// Paths to dotenv files, relative to the module's directory
+	pub env_file: Option<Vec<String>>,
 	pub envs: Option<Vec<(String, String)>>,
+	pub inject_env: Option<bool>,
+	pub user: Option<String>,
+	pub group: Option<String>,
+	pub groups: Option<Vec<String>>,
+	pub limits: Option<ModuleLimitsConfig>,
+	pub max_memory_restart: Option<MemoryRestartConfig>,
+	pub sandbox: Option<SandboxConfig>,
+	// Environment variables whose values are read from files or the secrets store
+	pub secrets: Option<HashMap<String, SecretConfig>>,
+	// Restarts the module when its files change
+	pub watch: Option<WatchConfig>,
+	// Runs this many copies of the module
+	pub instances: Option<InstanceCount>,
+	// How `guillotine reload` decides that the replacement is ready
+	pub reload: Option<ReloadConfig>,
+	// Runs the module on a schedule, instead of keeping it running
+	pub cron: Option<CronConfig>,
+	// Restarts the module on a schedule, given as a cron expression. Eg: "0 4 * * *"
+	pub restart_cron: Option<String>,
+	// Daemons are kept running, while oneshots are run to completion once. Defaults to daemon
+	#[serde(rename = "type")]
+	pub module_type: Option<ModuleType>,
+	// Modules that have to be running (or for oneshots, have completed) before this one starts
+	pub depends_on: Option<Vec<String>>,
+	// Commands run around the module's lifetime
+	pub hooks: Option<HooksConfig>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct HooksConfig {
+	// Run before the module is started. The module isn't started if it fails
+	pub pre_start: Option<HookConfig>,
+	pub post_start: Option<HookConfig>,
+	// Run before the module is asked to quit, and after it has
+	pub pre_stop: Option<HookConfig>,
+	pub post_stop: Option<HookConfig>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct HookConfig {
+	// Run by the shell, in the module's cwd and with the module's env
+	pub command: String,
+	// How long (in ms) the hook can run before it's killed. Defaults to 30000
+	pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModuleType {
+	Daemon,
+	Oneshot,
+}
+
+// Where a secret is read from. Either a file (relative to the module's
+// directory), or the name of a secret in the secrets store
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct SecretConfig {
+	pub file: Option<String>,
+	pub store: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct WatchConfig {
+	pub enabled: Option<bool>,
+	// Files and directories to watch, relative to the module's cwd. Defaults to the cwd
+	pub paths: Option<Vec<String>>,
+	// Glob patterns of paths not to watch. Defaults to .git and node_modules
+	pub ignore: Option<Vec<String>>,
+	// How long (in ms) to wait for the changes to stop before restarting
+	pub debounce: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct CronConfig {
+	// When to run the module, as a cron expression. Eg: "30 2 * * *" runs it at 2:30 AM
+	pub schedule: String,
+	// What to do when a run is due, but the previous one hasn't finished. Defaults to skip
+	pub overlap: Option<OverlapPolicy>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+	// Don't start the new run
+	Skip,
+	// Start the new run once the previous one finishes
+	Queue,
+	// Stop the previous run, and start the new one
+	Replace,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct ReloadConfig {
+	// How long (in ms) the replacement has to stay up before it's ready. Defaults to 1000
+	pub grace_period: Option<u64>,
+	// How long (in ms) to wait for the replacement before giving up on it. Defaults to 30000
+	pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct ModuleLimitsConfig {
+	pub open_files: Option<ResourceLimit>,
+	pub core_size: Option<ResourceLimit>,
+	pub address_space: Option<ResourceLimit>,
+	pub processes: Option<ResourceLimit>,
+	pub cgroup: Option<ModuleCgroupConfig>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct ModuleCgroupConfig {
+	pub memory_max: Option<ResourceLimit>,
+	// The number of CPUs worth of time the module can use. Eg: 0.5
+	pub cpu_max: Option<f64>,
+	pub pids_max: Option<ResourceLimit>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct MemoryRestartConfig {
+	pub threshold: ResourceLimit,
+	// How long (in ms) the module has to stay over the threshold before it's restarted
+	pub duration: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone)]
+pub struct SandboxConfig {
+	pub namespaces: Option<Vec<SandboxNamespace>>,
+	// Mounts the root, and every mount under it, read-only. Except for the
+	// module, log and writable_paths directories
+	pub read_only_root: Option<bool>,
+	pub writable_paths: Option<Vec<String>>,
+	pub chroot: Option<String>,
+	pub no_new_privs: Option<bool>,
+	pub seccomp: Option<SeccompPreset>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxNamespace {
+	Mount,
+	Pid,
+	Network,
+	Ipc,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SeccompPreset {
+	// Blocks syscalls that administer the system, rather than use it. That
+	// includes creating namespaces, whether with unshare or clone
+	Default,
+}
+
+// A limit on a resource, given either as a number, a size with a unit
+// ("512M", "2G") or as "unlimited"
+#[derive(Debug, Clone, Copy)]
+pub enum ResourceLimit {
+	Limited(u64),
+	Unlimited,
+}
+
+// A number, a size like "512M", or "unlimited"
+impl JsonSchema for ResourceLimit {
+	fn schema_name() -> String {
+		String::from("ResourceLimit")
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		SchemaObject {
+			subschemas: Some(Box::new(SubschemaValidation {
+				one_of: Some(vec![
+					SchemaObject {
+						instance_type: Some(InstanceType::Integer.into()),
+						..Default::default()
+					}
+					.into(),
+					SchemaObject {
+						instance_type: Some(InstanceType::String.into()),
+						string: Some(Box::new(schemars::schema::StringValidation {
+							pattern: Some(String::from("^([0-9]+[KkMmGgTt]?|unlimited|max)$")),
+							..Default::default()
+						})),
+						..Default::default()
+					}
+					.into(),
+				]),
+				..Default::default()
+			})),
+			..Default::default()
+		}
+		.into()
+	}
+}
+
+impl<'de> Deserialize<'de> for ResourceLimit {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		deserializer.deserialize_any(ResourceLimitVisitor)
+	}
+}
+
+struct ResourceLimitVisitor;
+
+impl<'de> Visitor<'de> for ResourceLimitVisitor {
+	type Value = ResourceLimit;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("a number, a size like \"512M\" or \"unlimited\"")
+	}
+
+	fn visit_u64<E: de::Error>(self, value: u64) -> Result<ResourceLimit, E> {
+		Ok(ResourceLimit::Limited(value))
+	}
+
+	fn visit_i64<E: de::Error>(self, value: i64) -> Result<ResourceLimit, E> {
+		if value < 0 {
+			Err(E::invalid_value(de::Unexpected::Signed(value), &self))
+		} else {
+			Ok(ResourceLimit::Limited(value as u64))
+		}
+	}
+
+	fn visit_str<E: de::Error>(self, value: &str) -> Result<ResourceLimit, E> {
+		let value = value.trim();
+		if value == "unlimited" || value == "max" {
+			return Ok(ResourceLimit::Unlimited);
+		}
+
+		let (number, multiplier) = match value.chars().last() {
+			Some('K') | Some('k') => (&value[..value.len() - 1], 1 << 10),
+			Some('M') | Some('m') => (&value[..value.len() - 1], 1 << 20),
+			Some('G') | Some('g') => (&value[..value.len() - 1], 1 << 30),
+			Some('T') | Some('t') => (&value[..value.len() - 1], 1 << 40),
+			_ => (value, 1),
+		};
+		match number
+			.trim()
+			.parse::<u64>()
+			.ok()
+			.and_then(|number| number.checked_mul(multiplier))
+		{
+			Some(number) => Ok(ResourceLimit::Limited(number)),
+			None => Err(E::invalid_value(de::Unexpected::Str(value), &self)),
+		}
+	}
+}
+
+// The number of instances of a module to run. Either a number, or "max" for
+// one per CPU
+#[derive(Debug, Clone, Copy)]
+pub enum InstanceCount {
+	Count(usize),
+	Max,
+}
+
+impl InstanceCount {
+	pub fn get(&self) -> usize {
+		match self {
+			InstanceCount::Count(count) => *count,
+			InstanceCount::Max => std::thread::available_parallelism()
+				.map(|count| count.get())
+				.unwrap_or(1),
+		}
+	}
+}
+
+impl JsonSchema for InstanceCount {
+	fn schema_name() -> String {
+		String::from("InstanceCount")
+	}
+
+	fn json_schema(_: &mut SchemaGenerator) -> Schema {
+		SchemaObject {
+			subschemas: Some(Box::new(SubschemaValidation {
+				one_of: Some(vec![
+					SchemaObject {
+						instance_type: Some(InstanceType::Integer.into()),
+						number: Some(Box::new(schemars::schema::NumberValidation {
+							minimum: Some(1.0),
+							..Default::default()
+						})),
+						..Default::default()
+					}
+					.into(),
+					SchemaObject {
+						enum_values: Some(vec![Value::String(String::from("max"))]),
+						..Default::default()
+					}
+					.into(),
+				]),
+				..Default::default()
+			})),
+			..Default::default()
+		}
+		.into()
+	}
+}
+
+impl<'de> Deserialize<'de> for InstanceCount {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		deserializer.deserialize_any(InstanceCountVisitor)
+	}
+}
+
+struct InstanceCountVisitor;
+
+impl<'de> Visitor<'de> for InstanceCountVisitor {
+	type Value = InstanceCount;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("a number of instances, or \"max\"")
+	}
+
+	fn visit_u64<E: de::Error>(self, value: u64) -> Result<InstanceCount, E> {
+		if value == 0 {
+			Err(E::invalid_value(de::Unexpected::Unsigned(value), &self))
+		} else {
+			Ok(InstanceCount::Count(value as usize))
+		}
+	}
+
+	fn visit_i64<E: de::Error>(self, value: i64) -> Result<InstanceCount, E> {
+		if value < 1 {
+			Err(E::invalid_value(de::Unexpected::Signed(value), &self))
+		} else {
+			Ok(InstanceCount::Count(value as usize))
+		}
+	}
+
+	fn visit_str<E: de::Error>(self, value: &str) -> Result<InstanceCount, E> {
+		if value == "max" {
+			Ok(InstanceCount::Max)
+		} else {
+			Err(E::invalid_value(de::Unexpected::Str(value), &self))
+		}
+	}
 }
 
 impl ModuleRunnerConfig {
@@ -64,8 +573,54 @@ impl ModuleRunnerConfig {
 			name: "Juno".to_string(),
 			command: path,
 			interpreter: None,
+			enabled: None,
 			args: Some(args),
+			cwd: None,
+			env: None,
+			env_file: None,
 			envs: None,
+			inject_env: None,
+			user: None,
+			group: None,
+			groups: None,
+			limits: None,
+			max_memory_restart: None,
+			sandbox: None,
+			secrets: None,
+			watch: None,
+			instances: None,
+			reload: None,
+			cron: None,
+			restart_cron: None,
+			module_type: None,
+			depends_on: None,
+			hooks: None,
 		}
 	}
 }
+
+// The schema of a GuillotineSpecificConfig, where none of the fields are required
+fn get_partial_config_schema(gen: &mut SchemaGenerator) -> Schema {
+	get_partial_schema::<GuillotineSpecificConfig>(gen)
+}
+
+// A map of module names to the parts of their manifest to change
+fn get_overrides_schema(gen: &mut SchemaGenerator) -> Schema {
+	SchemaObject {
+		instance_type: Some(vec![InstanceType::Object, InstanceType::Null].into()),
+		object: Some(Box::new(ObjectValidation {
+			additional_properties: Some(Box::new(get_partial_schema::<ModuleRunnerConfig>(gen))),
+			..Default::default()
+		})),
+		..Default::default()
+	}
+	.into()
+}
+
+fn get_partial_schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+	let mut schema = T::json_schema(gen).into_object();
+	if let Some(object) = schema.object.as_mut() {
+		object.required.clear();
+	}
+	schema.into()
+}
@@ -0,0 +1,219 @@
+use super::{parser, ConfigError, GuillotineModuleConfig, ModuleRunnerConfig};
+use async_std::{
+	fs,
+	path::{Path, PathBuf},
+	prelude::*,
+};
+use glob::Pattern;
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub struct DiscoveredModule {
+	pub dir: PathBuf,
+	pub manifest_path: PathBuf,
+	pub config: ModuleRunnerConfig,
+}
+
+#[derive(Default)]
+pub struct Discovery {
+	pub modules: Vec<DiscoveredModule>,
+	// Directories that weren't run as modules, and why
+	pub skipped: Vec<(PathBuf, String)>,
+	pub errors: Vec<ConfigError>,
+	// Overrides for modules that weren't found
+	pub unused_overrides: Vec<String>,
+}
+
+// Finds the modules in the module paths. A directory with a module manifest
+// is a module. Otherwise, its sub-directories are looked at (recursively, if
+// enabled) for modules. The overrides are applied to the modules' manifests
+pub async fn discover_modules(
+	config: &GuillotineModuleConfig,
+	overrides: Option<&HashMap<String, Value>>,
+) -> Discovery {
+	let mut discovery = Discovery::default();
+
+	let include = get_patterns(&config.include, "modules.include", &mut discovery);
+	let exclude = get_patterns(&config.exclude, "modules.exclude", &mut discovery);
+	let max_depth = if config.recursive.unwrap_or(false) {
+		config.max_depth.unwrap_or(usize::MAX)
+	} else {
+		1
+	};
+
+	let mut visited: Vec<PathBuf> = vec![];
+	for root in get_module_roots(config, &mut discovery) {
+		let root = match fs::canonicalize(&root).await {
+			Ok(root) => root,
+			Err(err) => {
+				discovery.skipped.push((root, format!("{}", err)));
+				continue;
+			}
+		};
+		// Directories to look at, along with how deep they are
+		let mut pending = vec![(root.clone(), 0)];
+		while let Some((dir, depth)) = pending.pop() {
+			let dir = match fs::canonicalize(&dir).await {
+				Ok(dir) => dir,
+				Err(_) => continue,
+			};
+			if visited.contains(&dir) {
+				continue;
+			}
+			visited.push(dir.clone());
+
+			// The root itself can be a module, when it's matched by a pattern
+			let relative_path = match dir.strip_prefix(&root) {
+				Ok(path) if !path.as_os_str().is_empty() => path.to_path_buf(),
+				_ => PathBuf::from(dir.file_name().unwrap_or_default()),
+			};
+			if exclude
+				.iter()
+				.any(|pattern| matches(pattern, &relative_path))
+			{
+				discovery.skipped.push((dir, String::from("excluded")));
+				continue;
+			}
+
+			match parser::read_module_manifest(&dir, overrides).await {
+				Ok(Some((manifest_path, module_config))) => {
+					if !include.is_empty()
+						&& !include
+							.iter()
+							.any(|pattern| matches(pattern, &relative_path))
+					{
+						discovery.skipped.push((dir, String::from("not included")));
+					} else if !module_config.enabled.unwrap_or(true) {
+						discovery.skipped.push((dir, String::from("disabled")));
+					} else {
+						discovery.modules.push(DiscoveredModule {
+							dir,
+							manifest_path,
+							config: module_config,
+						});
+					}
+					continue;
+				}
+				Ok(None) => {}
+				Err(err) => {
+					discovery.errors.push(err);
+					continue;
+				}
+			}
+
+			if depth >= max_depth {
+				if depth > 0 {
+					discovery
+						.skipped
+						.push((dir, String::from("no module manifest")));
+				}
+				continue;
+			}
+			let mut sub_dirs = get_sub_dirs(&dir).await;
+			if sub_dirs.is_empty() && depth > 0 {
+				discovery
+					.skipped
+					.push((dir, String::from("no module manifest")));
+				continue;
+			}
+			// Visit them in order, since they're popped off the end
+			sub_dirs.sort();
+			sub_dirs.reverse();
+			pending.extend(sub_dirs.into_iter().map(|sub_dir| (sub_dir, depth + 1)));
+		}
+	}
+
+	let mut unused_overrides: Vec<String> = overrides
+		.iter()
+		.flat_map(|overrides| overrides.keys())
+		.filter(|name| {
+			!discovery
+				.modules
+				.iter()
+				.any(|module| &&module.config.name == name)
+		})
+		.cloned()
+		.collect();
+	unused_overrides.sort();
+	discovery.unused_overrides = unused_overrides;
+
+	discovery
+}
+
+// Expands the module paths into directories
+fn get_module_roots(config: &GuillotineModuleConfig, discovery: &mut Discovery) -> Vec<PathBuf> {
+	let mut roots = vec![];
+	for (field, path) in config.get_paths() {
+		if !is_glob(&path) {
+			roots.push(PathBuf::from(path));
+			continue;
+		}
+		match glob::glob(&path) {
+			Ok(paths) => {
+				let mut paths: Vec<PathBuf> = paths
+					.filter_map(Result::ok)
+					.filter(|path| path.is_dir())
+					.map(PathBuf::from)
+					.collect();
+				paths.sort();
+				roots.extend(paths);
+			}
+			Err(err) => discovery.errors.push(ConfigError::new(
+				&field,
+				&format!("Invalid pattern: {}", err),
+			)),
+		}
+	}
+	roots
+}
+
+async fn get_sub_dirs(dir: &Path) -> Vec<PathBuf> {
+	let mut sub_dirs = vec![];
+	let mut entries = match dir.read_dir().await {
+		Ok(entries) => entries,
+		Err(_) => return sub_dirs,
+	};
+	while let Some(Ok(entry)) = entries.next().await {
+		let path = entry.path();
+		// Don't go looking in .git and the like
+		if entry.file_name().to_string_lossy().starts_with('.') {
+			continue;
+		}
+		if path.is_dir().await {
+			sub_dirs.push(path);
+		}
+	}
+	sub_dirs
+}
+
+fn get_patterns(
+	patterns: &Option<Vec<String>>,
+	field: &str,
+	discovery: &mut Discovery,
+) -> Vec<Pattern> {
+	let mut result = vec![];
+	for (index, pattern) in patterns.iter().flatten().enumerate() {
+		match Pattern::new(pattern) {
+			Ok(pattern) => result.push(pattern),
+			Err(err) => discovery.errors.push(ConfigError::new(
+				&format!("{}[{}]", field, index),
+				&format!("Invalid pattern: {}", err),
+			)),
+		}
+	}
+	result
+}
+
+// Patterns can match either the path relative to the module path, or just
+// the directory's name
+fn matches(pattern: &Pattern, relative_path: &Path) -> bool {
+	let relative_path: &std::path::Path = relative_path.as_ref();
+	pattern.matches_path(relative_path)
+		|| relative_path
+			.file_name()
+			.is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+}
+
+pub fn is_glob(path: &str) -> bool {
+	path.contains(['*', '?', '['])
+}
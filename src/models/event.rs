@@ -0,0 +1,52 @@
+use crate::models::CrashReason;
+
+// Something that happened to one of the modules guillotine runs
+#[derive(Debug, Clone)]
+pub struct Event {
+	pub id: u64,
+	pub timestamp: u64,
+	pub module_id: u64,
+	pub module: String,
+	pub kind: EventKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+	Started,
+	Restarted,
+	// How the process exited, if it wasn't successfully. Processes guillotine
+	// stops exit unsuccessfully too, but don't count as crashes
+	Exited {
+		result: Result<(), CrashReason>,
+		crashed: bool,
+	},
+	// The process couldn't be started
+	Errored(String),
+	// Whether the replacement took over, or why the previous process was kept
+	Reloaded(Result<(), String>),
+	JunoRestarted,
+}
+
+// The hooks triggered for each kind of event
+pub const EVENT_HOOKS: [&str; 6] = [
+	"processStarted",
+	"processRestarted",
+	"processExited",
+	"processErrored",
+	"processReloaded",
+	"juno.restarted",
+];
+
+impl EventKind {
+	// The Juno hook that's triggered for it. Eg: guillotine.processStarted
+	pub fn hook_name(&self) -> &'static str {
+		match self {
+			EventKind::Started => "processStarted",
+			EventKind::Restarted => "processRestarted",
+			EventKind::Exited { .. } => "processExited",
+			EventKind::Errored(_) => "processErrored",
+			EventKind::Reloaded(_) => "processReloaded",
+			EventKind::JunoRestarted => "juno.restarted",
+		}
+	}
+}
@@ -1,10 +1,20 @@
 mod cli_messages;
+mod config_error;
 mod config_types;
+mod event;
+mod process_info;
 
+pub mod discovery;
 pub mod parser;
 
 pub use cli_messages::GuillotineMessage;
+pub use config_error::ConfigError;
 pub use config_types::{
-	EnvRequirements, GuillotineConfig, GuillotineModuleConfig, GuillotinePerEnvConfig,
-	GuillotineSpecificConfig, ModuleRunnerConfig, ModuleRunningStatus,
+	CrashReason, EnvRequirements, GuillotineConfig, GuillotineModuleConfig, GuillotinePerEnvConfig,
+	GuillotineSpecificConfig, HookConfig, JunoConfig, MemoryRestartConfig, ModuleCgroupConfig,
+	ModuleLimitsConfig, ModuleRunnerConfig, ModuleRunningStatus, ModuleType, NotificationEvent,
+	NotificationTarget, NotificationsConfig, OverlapPolicy, ResourceLimit, SandboxNamespace,
+	SeccompPreset, SecretsStoreConfig, WatchConfig,
 };
+pub use event::{Event, EventKind, EVENT_HOOKS};
+pub use process_info::ProcessInfo;
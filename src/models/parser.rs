@@ -1,28 +1,728 @@
-use super::{EnvRequirements, GuillotineConfig, GuillotineSpecificConfig};
-use async_std::{fs, path::Path};
-use serde_json::{Error, Result};
-
-pub async fn select_config(input: String) -> Result<GuillotineSpecificConfig> {
-	let envs: GuillotineConfig = serde_json::from_str(&input)?;
-	if envs.config.is_some() {
-		parse_config(envs.config.unwrap()).await
+use super::{
+	discovery, ConfigError, EnvRequirements, GuillotineConfig, GuillotineSpecificConfig,
+	ModuleRunnerConfig,
+};
+use crate::utils::{constants, logger};
+use async_std::{
+	fs,
+	path::{Path, PathBuf},
+};
+use glob::Pattern;
+use serde::de::{
+	self, DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess,
+	Visitor,
+};
+use serde_json::Value;
+use std::{
+	collections::HashMap,
+	env,
+	fmt::{self, Display},
+};
+
+// The names a module's manifest can have, in order of preference
+pub const MODULE_MANIFESTS: [&str; 4] = ["module.json", "module.yaml", "module.yml", "module.toml"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+	Json,
+	Yaml,
+	Toml,
+}
+
+impl ConfigFormat {
+	// Detects the format from the file's extension, defaulting to JSON
+	pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Self {
+		match path
+			.as_ref()
+			.extension()
+			.map(|extension| extension.to_string_lossy().to_lowercase())
+			.as_deref()
+		{
+			Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+			Some("toml") => ConfigFormat::Toml,
+			_ => ConfigFormat::Json,
+		}
+	}
+}
+
+pub fn parse<T: DeserializeOwned>(input: &str, format: ConfigFormat) -> Result<T, ConfigError> {
+	match format {
+		ConfigFormat::Json => {
+			let mut deserializer = serde_json::Deserializer::from_str(input);
+			let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+				let (line, column) = (err.inner().line(), err.inner().column());
+				to_config_error(&err).at(line, column)
+			})?;
+			deserializer.end().map_err(|err| {
+				ConfigError::new("", &strip_location(&err.to_string())).at(err.line(), err.column())
+			})?;
+			Ok(value)
+		}
+		ConfigFormat::Yaml => {
+			let deserializer = serde_yaml::Deserializer::from_str(input);
+			serde_path_to_error::deserialize(deserializer).map_err(|err| {
+				let location = err.inner().location();
+				let error = to_config_error(&err);
+				match location {
+					Some(location) => error.at(location.line(), location.column()),
+					None => error,
+				}
+			})
+		}
+		ConfigFormat::Toml => {
+			let mut deserializer = toml::Deserializer::new(input);
+			serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+				let line_col = err.inner().line_col();
+				let error = to_config_error(&err);
+				match line_col {
+					Some((line, column)) => error.at(line + 1, column + 1),
+					None => error,
+				}
+			})
+		}
+	}
+}
+
+// Selects the config for this environment, and makes sure it can be run
+pub async fn select_config(
+	config_path: &Path,
+	input: String,
+	profile: Option<&str>,
+) -> Result<GuillotineSpecificConfig, Vec<ConfigError>> {
+	let (config, files) = read_config(config_path, &input).map_err(|err| vec![err])?;
+	let (config, sources) = select_env_config(config, files, profile).map_err(|err| vec![err])?;
+
+	let errors: Vec<_> = check_config(&config)
+		.await
+		.into_iter()
+		.map(|err| sources.locate(err))
+		.collect();
+	if !errors.is_empty() {
+		return Err(errors);
+	}
+	parse_config(config)
+		.await
+		.map_err(|errors| errors.into_iter().map(|err| sources.locate(err)).collect())
+}
+
+// Parses the config, along with the configs it includes
+pub fn read_config(
+	config_path: &Path,
+	input: &str,
+) -> Result<(GuillotineConfig, ConfigFiles), ConfigError> {
+	// Parse it on its own first, so that problems in it are reported with their location
+	let format = ConfigFormat::from_path(config_path);
+	let config: GuillotineConfig = parse(input, format)?;
+	if config.include.is_none() {
+		let files = ConfigFiles(vec![ConfigFile {
+			file: None,
+			input: input.to_string(),
+			format,
+			configs: config.configs.as_ref().map_or(0, Vec::len),
+		}]);
+		return Ok((config, files));
+	}
+
+	let config_path: &std::path::Path = config_path.as_ref();
+	let config_path = config_path
+		.canonicalize()
+		.unwrap_or_else(|_| config_path.to_path_buf());
+	let (document, files) = read_includes(&config_path, input, &mut vec![])?;
+	let files = ConfigFiles(files);
+	let config = serde_path_to_error::deserialize(document)
+		.map_err(|err| files.locate(to_config_error(&err)))?;
+	Ok((config, files))
+}
+
+// Merges the configs a config file includes (and the ones they include) in
+// order, followed by the file itself
+fn read_includes(
+	path: &std::path::Path,
+	input: &str,
+	including: &mut Vec<std::path::PathBuf>,
+) -> Result<(Value, Vec<ConfigFile>), ConfigError> {
+	let file = path.to_string_lossy().to_string();
+	let format = ConfigFormat::from_path(path);
+	let mut document: Value = parse(input, format).map_err(|err| err.in_file(&file))?;
+	let include = match document.get("include") {
+		Some(include) => serde_path_to_error::deserialize::<_, Vec<String>>(include.clone())
+			.map_err(|err| to_config_error(&err).with_prefix("include").in_file(&file))?,
+		None => vec![],
+	};
+
+	let config_file = ConfigFile {
+		// Problems in the config itself are reported with the name it was given
+		file: if including.is_empty() {
+			None
+		} else {
+			Some(file.clone())
+		},
+		input: input.to_string(),
+		format,
+		configs: document
+			.get("configs")
+			.and_then(Value::as_array)
+			.map_or(0, Vec::len),
+	};
+	including.push(path.to_path_buf());
+	let mut merged = Value::Null;
+	let mut included = vec![];
+	for (index, include_path) in include.iter().enumerate() {
+		let field = format!("include[{}]", index);
+		let include_path = path
+			.parent()
+			.map(|dir| dir.join(include_path))
+			.unwrap_or_else(|| include_path.into());
+		let contents = std::fs::read_to_string(&include_path).map_err(|err| {
+			ConfigError::new(
+				&field,
+				&format!(
+					"Error reading '{}': {}",
+					include_path.to_string_lossy(),
+					err
+				),
+			)
+			.in_file(&file)
+		})?;
+		let include_path = include_path.canonicalize().unwrap_or(include_path);
+		if including.contains(&include_path) {
+			return Err(ConfigError::new(
+				&field,
+				&format!(
+					"Including '{}' creates a loop",
+					include_path.to_string_lossy()
+				),
+			)
+			.in_file(&file));
+		}
+		let (value, files) = read_includes(&include_path, &contents, including)?;
+		merge_include(&mut merged, value);
+		included.push(files);
+	}
+	including.pop();
+
+	// Included configs don't need to be complete, or have a version of their own
+	if let Value::Object(document) = &mut document {
+		document.remove("include");
+	}
+	merge_include(&mut merged, document);
+	// The later files' configs take precedence, and are matched first
+	let files = std::iter::once(config_file)
+		.chain(included.into_iter().rev().flatten())
+		.collect();
+	Ok((merged, files))
+}
+
+// Merges an included config into the configs before it. The configs are
+// matched against the environment with the later files' ones first
+fn merge_include(base: &mut Value, mut value: Value) {
+	if let (Some(Value::Array(base_configs)), Some(Value::Array(configs))) = (
+		base.get_mut("configs").map(Value::take),
+		value.get_mut("configs"),
+	) {
+		configs.extend(base_configs);
+	}
+	merge(base, value);
+}
+
+// The files a config was read from, the config itself first and then the
+// configs it includes, from the last one to the first
+pub struct ConfigFiles(Vec<ConfigFile>);
+
+struct ConfigFile {
+	// None for the config itself
+	file: Option<String>,
+	input: String,
+	format: ConfigFormat,
+	// How many of the merged configs came from this file
+	configs: usize,
+}
+
+impl ConfigFiles {
+	// Points the error at the line and column of its field, in the file that
+	// the field came from
+	fn locate(&self, err: ConfigError) -> ConfigError {
+		let mut path: Vec<String> = match &err.field {
+			Some(field) => field
+				.replace("[", ".")
+				.replace("]", "")
+				.split('.')
+				.map(String::from)
+				.collect(),
+			None => return err,
+		};
+		// The files' configs are merged into one list, in the same order as the files
+		let index = match path.as_slice() {
+			[configs, index, ..] if configs == "configs" => index.parse::<usize>().ok(),
+			_ => None,
+		};
+		if let Some(mut index) = index {
+			for file in self.0.iter() {
+				if index < file.configs {
+					// The field is named the way it is in that file
+					let field = err.field.as_deref().unwrap_or_default();
+					let field = format!(
+						"configs[{}]{}",
+						index,
+						&field[field.find(']').unwrap_or(0) + 1..]
+					);
+					path[1] = index.to_string();
+					let err = ConfigError {
+						field: Some(field),
+						..err
+					};
+					return file.locate(err, &path);
+				}
+				index -= file.configs;
+			}
+			return err;
+		}
+		match self.0.iter().find(|file| file.find(&path).is_some()) {
+			Some(file) => file.locate(err, &path),
+			None => err,
+		}
+	}
+}
+
+impl ConfigFile {
+	fn locate(&self, err: ConfigError, path: &[String]) -> ConfigError {
+		let err = match self.find(path) {
+			Some((line, column)) => err.at(line, column),
+			None => return err,
+		};
+		match &self.file {
+			Some(file) => err.in_file(file),
+			None => err,
+		}
+	}
+
+	// Finds the line and column of the value at the path, by failing to
+	// deserialize it
+	fn find(&self, path: &[String]) -> Option<(usize, usize)> {
+		match self.format {
+			ConfigFormat::Json => {
+				let mut deserializer = serde_json::Deserializer::from_str(&self.input);
+				let err = Locator(path).deserialize(&mut deserializer).err()?;
+				Some((err.line(), err.column()))
+			}
+			ConfigFormat::Yaml => {
+				let deserializer = serde_yaml::Deserializer::from_str(&self.input);
+				let location = Locator(path).deserialize(deserializer).err()?.location()?;
+				Some((location.line(), location.column()))
+			}
+			ConfigFormat::Toml => {
+				let mut deserializer = toml::Deserializer::new(&self.input);
+				let (line, column) = Locator(path)
+					.deserialize(&mut deserializer)
+					.err()?
+					.line_col()?;
+				Some((line + 1, column + 1))
+			}
+		}
+	}
+}
+
+// Walks down the path, skipping everything else, and fails at the value at
+// the end of it. The formats add the location to the error
+struct Locator<'a>(&'a [String]);
+
+impl<'de, 'a> DeserializeSeed<'de> for Locator<'a> {
+	type Value = ();
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+		if self.0.is_empty() {
+			deserializer.deserialize_any(Found)
+		} else {
+			deserializer.deserialize_any(self)
+		}
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for Locator<'a> {
+	type Value = ();
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "a map or a sequence")
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+		// The rest is read even if the path isn't there, since the formats
+		// expect all of it to be
+		while let Some(key) = map.next_key::<String>()? {
+			if key == self.0[0] {
+				map.next_value_seed(Locator(&self.0[1..]))?;
+			} else {
+				map.next_value::<IgnoredAny>()?;
+			}
+		}
+		Ok(())
+	}
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+		let mut index = 0;
+		loop {
+			let element = if index.to_string() == self.0[0] {
+				seq.next_element_seed(Locator(&self.0[1..]))?
+			} else {
+				seq.next_element::<IgnoredAny>()?.map(|_| ())
+			};
+			if element.is_none() {
+				return Ok(());
+			}
+			index += 1;
+		}
+	}
+
+	// Anything else doesn't have the path in it
+	fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+		Ok(())
+	}
+
+	fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+		Ok(())
+	}
+
+	fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+		Ok(())
+	}
+
+	fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+		Ok(())
+	}
+
+	fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+		Ok(())
+	}
+
+	fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+		Ok(())
+	}
+}
+
+// Rejects any value, so that the error has its location
+struct Found;
+
+impl<'de> Visitor<'de> for Found {
+	type Value = ();
+
+	fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "nothing")
+	}
+}
+
+// The configs that the selected config was merged from, with their field
+// paths. Used to point errors at the config the problematic field came from
+pub struct ConfigSources {
+	sources: Vec<(String, Value)>,
+	files: ConfigFiles,
+}
+
+impl ConfigSources {
+	pub fn locate(&self, err: ConfigError) -> ConfigError {
+		let pointer = to_pointer(&err.field);
+		let prefix = self
+			.sources
+			.iter()
+			.find(|(_, config)| config.pointer(&pointer).is_some())
+			.or_else(|| self.sources.first())
+			.map(|(prefix, _)| prefix.clone())
+			.unwrap_or_default();
+		self.files.locate(err.with_prefix(&prefix))
+	}
+}
+
+// Gets the config that matches this environment. If there's a base config,
+// the matched config is merged into it
+pub fn select_env_config(
+	config: GuillotineConfig,
+	files: ConfigFiles,
+	profile: Option<&str>,
+) -> Result<(GuillotineSpecificConfig, ConfigSources), ConfigError> {
+	check_version(&config.version).map_err(|err| files.locate(err))?;
+	// Before 1.1.0, the base config was used as is, and the other configs were ignored
+	let configs = if config.version == "1.0.0" && config.config.is_some() {
+		vec![]
 	} else {
-		for config in envs.configs.unwrap().into_iter() {
-			if parse_if_config(&config.env).await? {
-				return parse_config(config.config).await;
+		config.configs.unwrap_or_default()
+	};
+	if let Some(profile) = profile {
+		if !configs
+			.iter()
+			.any(|config| config.env.profile.as_deref() == Some(profile))
+		{
+			return Err(files.locate(ConfigError::new(
+				"configs",
+				&format!("None of the configs have the profile '{}'", profile),
+			)));
+		}
+	}
+
+	let matched = configs
+		.into_iter()
+		.enumerate()
+		.find(|(_, config)| parse_if_config(&config.env, profile));
+	let sources = match (config.config, matched) {
+		(Some(base), Some((index, matched))) => vec![
+			(format!("configs[{}].config", index), matched.config),
+			(String::from("config"), base),
+		],
+		(None, Some((index, matched))) => {
+			vec![(format!("configs[{}].config", index), matched.config)]
+		}
+		(Some(base), None) => vec![(String::from("config"), base)],
+		(None, None) => {
+			return Err(files.locate(ConfigError::new(
+				"configs",
+				"None of the configs match this environment, and there's no base config",
+			)))
+		}
+	};
+
+	let mut merged = Value::Null;
+	for (_, config) in sources.iter().rev() {
+		merge(&mut merged, config.clone());
+	}
+	let sources = ConfigSources { sources, files };
+	let config = serde_path_to_error::deserialize(merged)
+		.map_err(|err| sources.locate(to_config_error(&err)))?;
+
+	Ok((config, sources))
+}
+
+// Makes sure this binary can read the config. Older versions are still read,
+// but should be migrated
+pub fn check_version(version: &str) -> Result<(), ConfigError> {
+	if !constants::SUPPORTED_CONFIG_VERSIONS.contains(&version) {
+		return Err(ConfigError::new(
+			"version",
+			&format!(
+				"Unsupported config version '{}'. Supported versions are: {}",
+				version,
+				constants::SUPPORTED_CONFIG_VERSIONS.join(", ")
+			),
+		));
+	}
+	if version != constants::CONFIG_VERSION {
+		logger::warn(&format!(
+			"The config is at version {}. Run `guillotine migrate-config` to upgrade it to {}",
+			version,
+			constants::CONFIG_VERSION
+		));
+	}
+	Ok(())
+}
+
+// Converts a field path to a JSON pointer. Eg: configs[1].config -> /configs/1/config
+fn to_pointer(field: &Option<String>) -> String {
+	match field {
+		Some(field) => format!(
+			"/{}",
+			field.replace("[", ".").replace("]", "").replace(".", "/")
+		),
+		None => String::new(),
+	}
+}
+
+// Merges the value into the base. Objects are merged key by key, everything
+// else is replaced
+fn merge(base: &mut Value, value: Value) {
+	match (base, value) {
+		(Value::Object(base), Value::Object(value)) => {
+			for (key, value) in value {
+				merge(base.entry(key).or_insert(Value::Null), value);
 			}
 		}
-		throw_parse_error()
+		(base, value) => *base = value,
 	}
 }
 
-fn throw_parse_error() -> Result<GuillotineSpecificConfig> {
-	Err(Error::io(std::io::Error::from(
-		std::io::ErrorKind::InvalidData,
-	)))
+// Checks the parts of the config that can't be checked while parsing it.
+// Returns all the problems found
+pub async fn check_config(config: &GuillotineSpecificConfig) -> Vec<ConfigError> {
+	let mut errors = vec![];
+
+	if !Path::new(&config.juno.path).exists().await {
+		errors.push(ConfigError::new(
+			"juno.path",
+			&format!("'{}' doesn't exist", config.juno.path),
+		));
+	}
+
+	match config.juno.connection_type.as_str() {
+		"unix_socket" => match &config.juno.socket_path {
+			Some(socket_path) => {
+				let parent = Path::new(socket_path).parent();
+				if let Some(parent) = parent.filter(|parent| !parent.as_os_str().is_empty()) {
+					if !parent.exists().await {
+						errors.push(ConfigError::new(
+							"juno.socket_path",
+							&format!("The directory '{}' doesn't exist", parent.to_string_lossy()),
+						));
+					}
+				}
+			}
+			None => errors.push(ConfigError::new(
+				"juno.socket_path",
+				"Expected a socket_path for the unix_socket connection_type",
+			)),
+		},
+		"inet_socket" => {}
+		connection_type => errors.push(ConfigError::new(
+			"juno.connection_type",
+			&format!(
+				"Expected `unix_socket` or `inet_socket`, found `{}`",
+				connection_type
+			),
+		)),
+	}
+
+	if let Some(secrets_store) = &config.secrets_store {
+		if !Path::new(&secrets_store.path).is_file().await {
+			errors.push(ConfigError::new(
+				"secrets_store.path",
+				&format!("'{}' doesn't exist", secrets_store.path),
+			));
+		}
+	}
+
+	if let Some(notifications) = &config.notifications {
+		for (index, target) in notifications.targets.iter().enumerate() {
+			let field = format!("notifications.targets[{}]", index);
+			match (&target.webhook, &target.command) {
+				(Some(webhook), None) => {
+					if !webhook.starts_with("http://") && !webhook.starts_with("https://") {
+						errors.push(ConfigError::new(
+							&format!("{}.webhook", field),
+							&format!("'{}' isn't an http or https URL", webhook),
+						));
+					}
+					if !is_on_path("curl") {
+						errors.push(ConfigError::new(
+							&format!("{}.webhook", field),
+							"Webhooks are sent with curl, which isn't on the PATH",
+						));
+					}
+				}
+				(None, Some(_)) => {}
+				_ => errors.push(ConfigError::new(
+					&field,
+					"Expected either `webhook` or `command`",
+				)),
+			}
+			if target.rate_limit.as_ref().and_then(|limit| limit.count) == Some(0) {
+				errors.push(ConfigError::new(
+					&format!("{}.rate_limit.count", field),
+					"Expected at least 1",
+				));
+			}
+		}
+	}
+
+	for (index, process) in config.processes.iter().flatten().enumerate() {
+		// Disabled processes aren't run, so there's nothing to check
+		if !process.enabled.unwrap_or(true) {
+			continue;
+		}
+		match &process.cwd {
+			Some(cwd) if !Path::new(cwd).is_dir().await => errors.push(ConfigError::new(
+				&format!("processes[{}].cwd", index),
+				&format!("'{}' isn't a directory", cwd),
+			)),
+			Some(_) => {}
+			None => errors.push(ConfigError::new(
+				&format!("processes[{}].cwd", index),
+				"Expected a cwd for processes declared in the config",
+			)),
+		}
+	}
+
+	if let Some(modules) = &config.modules {
+		let paths = modules.get_paths();
+		if paths.is_empty() {
+			errors.push(ConfigError::new(
+				"modules",
+				"Expected either `path` or `paths` to be set",
+			));
+		}
+		for (field, path) in paths {
+			if !discovery::is_glob(&path) && !Path::new(&path).is_dir().await {
+				errors.push(ConfigError::new(
+					&field,
+					&format!("'{}' isn't a directory", path),
+				));
+			}
+		}
+	}
+
+	errors
 }
 
-async fn parse_if_config(input: &EnvRequirements) -> Result<bool> {
+// Finds and parses the manifest in a module's directory, if it has one.
+// The module's overrides (if any) are merged into it
+pub async fn read_module_manifest(
+	dir: &Path,
+	overrides: Option<&HashMap<String, Value>>,
+) -> Result<Option<(PathBuf, ModuleRunnerConfig)>, ConfigError> {
+	for manifest_name in MODULE_MANIFESTS.iter() {
+		let manifest_path = dir.join(manifest_name);
+		if !manifest_path.exists().await {
+			continue;
+		}
+		let file = manifest_path.to_string_lossy().to_string();
+		let contents = fs::read_to_string(&manifest_path)
+			.await
+			.map_err(|err| ConfigError::new("", &format!("{}", err)).in_file(&file))?;
+		let format = ConfigFormat::from_path(&manifest_path);
+		let config: ModuleRunnerConfig =
+			parse(&contents, format).map_err(|err| err.in_file(&file))?;
+		let module_overrides = match overrides.and_then(|overrides| overrides.get(&config.name)) {
+			Some(module_overrides) => module_overrides,
+			None => return Ok(Some((manifest_path, config))),
+		};
+
+		let mut document: Value = parse(&contents, format).map_err(|err| err.in_file(&file))?;
+		merge(&mut document, module_overrides.clone());
+		let config = serde_path_to_error::deserialize(document).map_err(|err| {
+			let err = to_config_error(&err);
+			// Point at the override if that's where the problem is
+			if module_overrides.pointer(&to_pointer(&err.field)).is_some() {
+				err.with_prefix(&format!("overrides.{}", config.name))
+			} else {
+				err.in_file(&file)
+			}
+		})?;
+		return Ok(Some((manifest_path, config)));
+	}
+	Ok(None)
+}
+
+fn to_config_error<E: Display>(err: &serde_path_to_error::Error<E>) -> ConfigError {
+	let field = err.path().to_string();
+	let field = if field == "." { String::new() } else { field };
+
+	let message = strip_location(&err.inner().to_string());
+	// Some formats add the field path to the message themselves
+	let message = message
+		.strip_prefix(&format!("{}: ", field))
+		.unwrap_or(&message)
+		.to_string();
+	ConfigError::new(&field, &message)
+}
+
+// Removes the location (" at line X column Y" or " for key `x`") that the
+// formats add to the end of messages
+fn strip_location(message: &str) -> String {
+	let message = match message.rfind(" at line ") {
+		Some(index) => &message[..index],
+		None => message,
+	};
+	match message.rfind(" for key `") {
+		Some(index) => message[..index].to_string(),
+		None => message.to_string(),
+	}
+}
+
+fn parse_if_config(input: &EnvRequirements, profile: Option<&str>) -> bool {
 	let mut satisfied = true;
 
 	if let Some(required_cfg) = &input.target_family {
@@ -77,60 +777,119 @@ async fn parse_if_config(input: &EnvRequirements) -> Result<bool> {
 		}
 	}
 
-	Ok(satisfied)
+	if let Some(required_profile) = &input.profile {
+		satisfied &= profile == Some(required_profile.as_str());
+	}
+
+	if let Some(hostname_pattern) = &input.hostname {
+		satisfied &= match (Pattern::new(hostname_pattern), get_hostname()) {
+			(Ok(pattern), Some(hostname)) => pattern.matches(&hostname),
+			_ => false,
+		};
+	}
+
+	if let Some(env_vars) = &input.env_vars {
+		satisfied &= env_vars
+			.iter()
+			.all(|(key, value)| env::var(key).ok().as_ref() == Some(value));
+	}
+
+	if let Some(file) = &input.file_exists {
+		satisfied &= std::path::Path::new(file).exists();
+	}
+
+	satisfied
 }
 
-async fn parse_config(mut input: GuillotineSpecificConfig) -> Result<GuillotineSpecificConfig> {
-	if input.modules.is_some() {
-		let mut modules = input.modules.unwrap();
-		modules.path = fs::canonicalize(modules.path)
-			.await
-			.unwrap()
-			.to_str()
-			.unwrap()
-			.to_string();
-		if modules.logs.is_some() {
-			modules.logs = Some(
-				fs::canonicalize(modules.logs.unwrap())
-					.await
-					.unwrap()
-					.to_str()
-					.unwrap()
-					.to_string(),
-			);
+fn is_on_path(program: &str) -> bool {
+	let program = format!("{}{}", program, std::env::consts::EXE_SUFFIX);
+	std::env::var_os("PATH")
+		.is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(&program).is_file()))
+}
+
+#[cfg(target_family = "unix")]
+pub fn get_hostname() -> Option<String> {
+	let mut buffer = [0u8; 256];
+	nix::unistd::gethostname(&mut buffer)
+		.ok()
+		.map(|hostname| hostname.to_string_lossy().to_string())
+}
+
+#[cfg(target_family = "windows")]
+pub fn get_hostname() -> Option<String> {
+	env::var("COMPUTERNAME").ok()
+}
+
+// Resolves the paths in the config and fills in the defaults.
+// The config is expected to have been checked already
+async fn parse_config(
+	mut input: GuillotineSpecificConfig,
+) -> Result<GuillotineSpecificConfig, Vec<ConfigError>> {
+	if let Some(mut modules) = input.modules.take() {
+		if let Some(path) = modules.path.take() {
+			// Patterns are expanded when looking for modules
+			if discovery::is_glob(&path) {
+				modules.path = Some(path);
+			} else {
+				modules.path = Some(canonicalize(&path, "modules.path").await?);
+			}
+		}
+		if let Some(paths) = modules.paths.take() {
+			let mut canonical_paths = vec![];
+			for (index, path) in paths.into_iter().enumerate() {
+				// Patterns are expanded when looking for modules
+				if discovery::is_glob(&path) {
+					canonical_paths.push(path);
+				} else {
+					canonical_paths
+						.push(canonicalize(&path, &format!("modules.paths[{}]", index)).await?);
+				}
+			}
+			modules.paths = Some(canonical_paths);
+		}
+		if let Some(logs) = modules.logs {
+			if !Path::new(&logs).exists().await {
+				fs::create_dir_all(&logs).await.map_err(|err| {
+					vec![ConfigError::new(
+						"modules.logs",
+						&format!("Error creating '{}': {}", logs, err),
+					)]
+				})?;
+			}
+			modules.logs = Some(canonicalize(&logs, "modules.logs").await?);
 		}
 		input.modules = Some(modules);
 	}
 
-	input.juno.path = fs::canonicalize(input.juno.path)
-		.await
-		.unwrap()
-		.to_str()
-		.unwrap()
-		.to_string();
+	if let Some(processes) = input.processes.as_mut() {
+		for (index, process) in processes.iter_mut().enumerate() {
+			if !process.enabled.unwrap_or(true) {
+				continue;
+			}
+			let cwd = process.cwd.take().unwrap();
+			process.cwd = Some(canonicalize(&cwd, &format!("processes[{}].cwd", index)).await?);
+		}
+	}
+
+	input.juno.path = canonicalize(&input.juno.path, "juno.path").await?;
+	if let Some(secrets_store) = input.secrets_store.as_mut() {
+		secrets_store.path = canonicalize(&secrets_store.path, "secrets_store.path").await?;
+	}
 
 	if input.juno.connection_type == "unix_socket" {
-		if input.juno.socket_path.is_none() {
-			return throw_parse_error();
-		}
-		let socket_path = &input.juno.socket_path.unwrap();
-		let socket_path = Path::new(socket_path);
+		let socket_path = input.juno.socket_path.take().unwrap();
 
-		if !socket_path.exists().await {
-			fs::write(socket_path, "").await.unwrap();
+		if !Path::new(&socket_path).exists().await {
+			fs::write(&socket_path, "").await.map_err(|err| {
+				vec![ConfigError::new(
+					"juno.socket_path",
+					&format!("Error creating '{}': {}", socket_path, err),
+				)]
+			})?;
 		}
 
-		input.juno.socket_path = Some(
-			fs::canonicalize(socket_path)
-				.await
-				.unwrap()
-				.to_str()
-				.unwrap()
-				.to_string(),
-		);
-
-		Ok(input)
-	} else if input.juno.connection_type == "inet_socket" {
+		input.juno.socket_path = Some(canonicalize(&socket_path, "juno.socket_path").await?);
+	} else {
 		if input.juno.port.is_none() {
 			input.juno.port = Some(2203);
 		}
@@ -138,9 +897,19 @@ async fn parse_config(mut input: GuillotineSpecificConfig) -> Result<GuillotineS
 		if input.juno.bind_addr.is_none() {
 			input.juno.bind_addr = Some("127.0.0.1".to_string());
 		}
-
-		Ok(input)
-	} else {
-		throw_parse_error()
 	}
+
+	Ok(input)
+}
+
+async fn canonicalize(path: &str, field: &str) -> Result<String, Vec<ConfigError>> {
+	fs::canonicalize(path)
+		.await
+		.map(|path| path.to_string_lossy().to_string())
+		.map_err(|err| {
+			vec![ConfigError::new(
+				field,
+				&format!("Error resolving '{}': {}", path, err),
+			)]
+		})
 }
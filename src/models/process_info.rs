@@ -0,0 +1,22 @@
+use crate::models::{CrashReason, ModuleRunnerConfig, ModuleRunningStatus};
+
+// A snapshot of one of the processes guillotine runs
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+	pub module_id: u64,
+	// With the instance's index, if the module is run in cluster mode. Eg: app:1
+	pub name: String,
+	pub status: ModuleRunningStatus,
+	// Whether a replacement for it is being started
+	pub reloading: bool,
+	pub restarts: i64,
+	pub uptime: u64,
+	pub crashes: u64,
+	pub crash_reason: Option<CrashReason>,
+	pub created_at: u64,
+	// When a cron job is run next, or when a module with a restart_cron is restarted next
+	pub next_run: Option<u64>,
+	// How a cron job's last run ended
+	pub last_run: Option<Result<(), CrashReason>>,
+	pub config: ModuleRunnerConfig,
+}
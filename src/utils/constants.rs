@@ -4,3 +4,7 @@ pub const APP_NAME: &str = crate_name!();
 pub const APP_VERSION: &str = crate_version!();
 pub const APP_AUTHORS: &str = crate_authors!();
 pub const APP_ABOUT: &str = crate_description!();
+
+// The version of the config file format this binary writes, and the ones it can read
+pub const CONFIG_VERSION: &str = "1.1.0";
+pub const SUPPORTED_CONFIG_VERSIONS: [&str; 2] = ["1.0.0", "1.1.0"];
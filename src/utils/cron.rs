@@ -0,0 +1,306 @@
+use chrono::{prelude::*, Duration};
+
+const MONTHS: [&str; 12] = [
+	"jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// A cron-style schedule, in local time. Each field is a bitmask of the values it allows
+#[derive(Debug, Clone)]
+pub struct Schedule {
+	minutes: u64,
+	hours: u64,
+	days: u64,
+	months: u64,
+	weekdays: u64,
+	// When both the day of the month and the day of the week are
+	// restricted, a day matching either of them is run on (like cron does)
+	days_restricted: bool,
+	weekdays_restricted: bool,
+}
+
+impl Schedule {
+	// Parses "minute hour day-of-month month day-of-week", where each field is *, a
+	// number, a range (1-5), a step (*/15, 1-30/2) or a list of them (1,15). Months
+	// and days of the week can be names (jan, mon). @hourly, @daily, @weekly,
+	// @monthly and @yearly can be used instead
+	pub fn parse(expression: &str) -> Result<Self, String> {
+		let expression = match expression.trim() {
+			"@yearly" | "@annually" => "0 0 1 1 *",
+			"@monthly" => "0 0 1 * *",
+			"@weekly" => "0 0 * * 0",
+			"@daily" | "@midnight" => "0 0 * * *",
+			"@hourly" => "0 * * * *",
+			expression => expression,
+		};
+		let fields: Vec<&str> = expression.split_whitespace().collect();
+		if fields.len() != 5 {
+			return Err(format!(
+				"Expected 5 fields (minute, hour, day of month, month and day of week). Got {}",
+				fields.len()
+			));
+		}
+
+		let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS)?;
+		// Both 0 and 7 are Sunday
+		if weekdays & (1 << 7) != 0 {
+			weekdays = (weekdays & !(1 << 7)) | 1;
+		}
+		Ok(Schedule {
+			minutes: parse_field(fields[0], 0, 59, &[])?,
+			hours: parse_field(fields[1], 0, 23, &[])?,
+			days: parse_field(fields[2], 1, 31, &[])?,
+			months: parse_field(fields[3], 1, 12, &MONTHS)?,
+			weekdays,
+			days_restricted: !fields[2].starts_with('*'),
+			weekdays_restricted: !fields[4].starts_with('*'),
+		})
+	}
+
+	// The first time after the given one that the schedule matches
+	pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+		let mut next =
+			time.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+		// Give up if nothing matches for a few years. Eg: the 30th of February
+		let limit = next + Duration::days(366 * 5);
+		while next < limit {
+			if !self.matches_day(next.date()) {
+				next = (next.date() + Duration::days(1)).and_hms(0, 0, 0);
+			} else if !has_bit(self.hours, next.hour()) {
+				next = next.date().and_hms(next.hour(), 0, 0) + Duration::hours(1);
+			} else if !has_bit(self.minutes, next.minute()) {
+				next += Duration::minutes(1);
+			} else {
+				// Times skipped by daylight saving don't exist, so they're passed over
+				if let Some(time) = Local.from_local_datetime(&next).earliest() {
+					return Some(time);
+				}
+				next += Duration::minutes(1);
+			}
+		}
+		None
+	}
+
+	fn matches_day(&self, date: NaiveDate) -> bool {
+		if !has_bit(self.months, date.month()) {
+			return false;
+		}
+		let day = has_bit(self.days, date.day());
+		let weekday = has_bit(self.weekdays, date.weekday().num_days_from_sunday());
+		if self.days_restricted && self.weekdays_restricted {
+			day || weekday
+		} else {
+			day && weekday
+		}
+	}
+}
+
+fn has_bit(mask: u64, bit: u32) -> bool {
+	mask & (1 << bit) != 0
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+	let mut mask = 0;
+	for part in field.split(',') {
+		let (range, step) = match part.find('/') {
+			Some(index) => {
+				let step = &part[index + 1..];
+				match step.parse::<u32>() {
+					Ok(step) if step > 0 => (&part[..index], step),
+					_ => return Err(format!("'{}' is not a valid step", step)),
+				}
+			}
+			None => (part, 1),
+		};
+		let (start, end) = if range == "*" {
+			(min, max)
+		} else if let Some(index) = range.find('-') {
+			(
+				parse_value(&range[..index], min, max, names)?,
+				parse_value(&range[index + 1..], min, max, names)?,
+			)
+		} else {
+			let value = parse_value(range, min, max, names)?;
+			// A single value with a step, like 5/10, goes on till the end
+			(value, if part.contains('/') { max } else { value })
+		};
+		if start > end {
+			return Err(format!("'{}' is not a valid range", range));
+		}
+		for value in (start..=end).step_by(step as usize) {
+			mask |= 1 << value;
+		}
+	}
+	Ok(mask)
+}
+
+fn parse_value(value: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, String> {
+	let lowercase = value.to_lowercase();
+	// Names count from the lowest value of the field. Eg: jan is 1, and sun is 0
+	if let Some(index) = names.iter().position(|name| *name == lowercase) {
+		return Ok(min + index as u32);
+	}
+	match value.parse::<u32>() {
+		Ok(number) if number >= min && number <= max => Ok(number),
+		Ok(_) => Err(format!("'{}' is out of range ({}-{})", value, min, max)),
+		Err(_) => Err(format!("'{}' is not a valid value", value)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn time(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+		Local.ymd(year, month, day).and_hms(hour, minute, 0)
+	}
+
+	fn next(expression: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+		Schedule::parse(expression).unwrap().next_after(after)
+	}
+
+	#[test]
+	fn parses_steps() {
+		let schedule = Schedule::parse("*/15 * * * *").unwrap();
+		assert_eq!(
+			schedule.minutes,
+			(1 << 0) | (1 << 15) | (1 << 30) | (1 << 45)
+		);
+	}
+
+	#[test]
+	fn parses_ranges() {
+		let schedule = Schedule::parse("0 1-5 * * *").unwrap();
+		assert_eq!(schedule.hours, 0b111110);
+	}
+
+	#[test]
+	fn parses_names() {
+		let schedule = Schedule::parse("0 0 * * MON-FRI").unwrap();
+		assert_eq!(schedule.weekdays, 0b0111110);
+		let schedule = Schedule::parse("0 0 * jan,Dec *").unwrap();
+		assert_eq!(schedule.months, (1 << 1) | (1 << 12));
+	}
+
+	#[test]
+	fn treats_7_as_sunday() {
+		let schedule = Schedule::parse("0 0 * * 7").unwrap();
+		assert_eq!(schedule.weekdays, 1);
+	}
+
+	#[test]
+	fn runs_daily() {
+		assert_eq!(
+			next("0 4 * * *", time(2020, 1, 1, 10, 0)),
+			Some(time(2020, 1, 2, 4, 0))
+		);
+		assert_eq!(
+			next("0 4 * * *", time(2020, 1, 1, 3, 59)),
+			Some(time(2020, 1, 1, 4, 0))
+		);
+		// A time that matches exactly isn't after itself
+		assert_eq!(
+			next("0 4 * * *", time(2020, 1, 1, 4, 0)),
+			Some(time(2020, 1, 2, 4, 0))
+		);
+	}
+
+	#[test]
+	fn runs_on_weekdays() {
+		// The 4th of January 2020 is a Saturday
+		assert_eq!(
+			next("30 9 * * mon-fri", time(2020, 1, 4, 12, 0)),
+			Some(time(2020, 1, 6, 9, 30))
+		);
+	}
+
+	#[test]
+	fn matches_either_restricted_day() {
+		// Every Friday, and every 13th. The 1st of March 2020 is a Sunday
+		let schedule = Schedule::parse("0 0 13 * fri").unwrap();
+		assert_eq!(
+			schedule.next_after(time(2020, 3, 1, 0, 0)),
+			Some(time(2020, 3, 6, 0, 0))
+		);
+		// The 13th of April 2020 is a Monday
+		assert_eq!(
+			schedule.next_after(time(2020, 4, 10, 0, 0)),
+			Some(time(2020, 4, 13, 0, 0))
+		);
+		assert_eq!(
+			schedule.next_after(time(2020, 4, 13, 0, 0)),
+			Some(time(2020, 4, 17, 0, 0))
+		);
+	}
+
+	#[test]
+	fn matches_both_days_when_one_is_unrestricted() {
+		assert_eq!(
+			next("0 0 13 * *", time(2020, 3, 1, 0, 0)),
+			Some(time(2020, 3, 13, 0, 0))
+		);
+		assert_eq!(
+			next("0 0 * * fri", time(2020, 3, 1, 0, 0)),
+			Some(time(2020, 3, 6, 0, 0))
+		);
+	}
+
+	#[test]
+	fn crosses_month_boundaries() {
+		assert_eq!(
+			next("30 12 1 * *", time(2020, 1, 31, 13, 0)),
+			Some(time(2020, 2, 1, 12, 30))
+		);
+		assert_eq!(
+			next("0 0 31 * *", time(2020, 4, 1, 0, 0)),
+			Some(time(2020, 5, 31, 0, 0))
+		);
+	}
+
+	#[test]
+	fn crosses_year_boundaries() {
+		assert_eq!(
+			next("0 0 1 1 *", time(2020, 6, 15, 0, 0)),
+			Some(time(2021, 1, 1, 0, 0))
+		);
+		assert_eq!(
+			next("@yearly", time(2020, 12, 31, 23, 59)),
+			Some(time(2021, 1, 1, 0, 0))
+		);
+		assert_eq!(
+			next("59 23 31 12 *", time(2020, 12, 31, 23, 59)),
+			Some(time(2021, 12, 31, 23, 59))
+		);
+		assert_eq!(
+			next("0 0 29 2 *", time(2021, 3, 1, 0, 0)),
+			Some(time(2024, 2, 29, 0, 0))
+		);
+	}
+
+	#[test]
+	fn gives_up_on_days_that_never_come() {
+		assert_eq!(next("0 0 30 2 *", time(2020, 1, 1, 0, 0)), None);
+	}
+
+	#[test]
+	fn rejects_out_of_range_values() {
+		assert!(Schedule::parse("60 * * * *").is_err());
+		assert!(Schedule::parse("* 24 * * *").is_err());
+		assert!(Schedule::parse("* * 0 * *").is_err());
+		assert!(Schedule::parse("* * * 13 *").is_err());
+		assert!(Schedule::parse("* * * * 8").is_err());
+	}
+
+	#[test]
+	fn rejects_the_wrong_number_of_fields() {
+		assert!(Schedule::parse("* * * *").is_err());
+		assert!(Schedule::parse("* * * * * *").is_err());
+	}
+
+	#[test]
+	fn rejects_invalid_ranges_and_steps() {
+		assert!(Schedule::parse("5-1 * * * *").is_err());
+		assert!(Schedule::parse("*/0 * * * *").is_err());
+		assert!(Schedule::parse("a * * * *").is_err());
+	}
+}
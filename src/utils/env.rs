@@ -0,0 +1,268 @@
+use crate::models::{JunoConfig, ModuleRunnerConfig};
+use std::{env, fs, path::Path};
+
+// Gets the environment variables a module is run with. Each source overrides
+// the ones before it: the global env, env files (in order), env, and envs
+pub fn get_module_envs(
+	global_envs: &[(String, String)],
+	config: &ModuleRunnerConfig,
+	working_dir: &str,
+) -> Result<Vec<(String, String)>, String> {
+	let mut envs: Vec<(String, String)> = global_envs
+		.iter()
+		.map(|(key, value)| (key.clone(), interpolate(value)))
+		.collect();
+
+	for env_file in config.env_file.as_ref().unwrap_or(&vec![]) {
+		let env_file = Path::new(working_dir).join(env_file);
+		let contents = fs::read_to_string(&env_file).map_err(|err| {
+			format!(
+				"Error reading env file '{}': {}",
+				env_file.to_string_lossy(),
+				err
+			)
+		})?;
+		envs.extend(parse_env_file(&contents).map_err(|err| {
+			format!(
+				"Error parsing env file '{}': {}",
+				env_file.to_string_lossy(),
+				err
+			)
+		})?);
+	}
+
+	if let Some(env) = &config.env {
+		let mut env: Vec<_> = env.iter().collect();
+		env.sort();
+		envs.extend(
+			env.into_iter()
+				.map(|(key, value)| (key.clone(), interpolate(value))),
+		);
+	}
+	if let Some(legacy_envs) = &config.envs {
+		envs.extend(legacy_envs.iter().cloned());
+	}
+
+	Ok(envs)
+}
+
+// Gets the variables that tell a module how to connect to Juno, and about itself
+pub fn get_guillotine_envs(
+	juno: &JunoConfig,
+	module_id: u64,
+	module_name: &str,
+	log_dir: &Option<String>,
+) -> Vec<(String, String)> {
+	let mut envs = vec![(
+		String::from("JUNO_CONNECTION_TYPE"),
+		juno.connection_type.clone(),
+	)];
+	if let Some(socket_path) = &juno.socket_path {
+		envs.push((String::from("JUNO_SOCKET_PATH"), socket_path.clone()));
+	}
+	if let Some(port) = juno.port {
+		envs.push((String::from("JUNO_PORT"), port.to_string()));
+	}
+	if let Some(bind_addr) = &juno.bind_addr {
+		envs.push((String::from("JUNO_BIND_ADDR"), bind_addr.clone()));
+	}
+	envs.push((String::from("GUILLOTINE_MODULE_ID"), module_id.to_string()));
+	envs.push((
+		String::from("GUILLOTINE_MODULE_NAME"),
+		module_name.to_string(),
+	));
+	if let Some(log_dir) = log_dir {
+		envs.push((String::from("GUILLOTINE_LOG_DIR"), log_dir.clone()));
+	}
+	envs
+}
+
+// Replaces ${VAR} and ${VAR:-default} with values from guillotine's environment.
+// Unset variables are replaced with the default, or an empty string
+pub fn interpolate(value: &str) -> String {
+	let mut result = String::new();
+	let mut remaining = value;
+
+	while let Some(start) = remaining.find("${") {
+		result.push_str(&remaining[..start]);
+		let expression = &remaining[start + 2..];
+		let end = match expression.find('}') {
+			Some(end) => end,
+			None => {
+				// Not a variable, leave it as is
+				result.push_str(&remaining[start..]);
+				return result;
+			}
+		};
+
+		let expression = &expression[..end];
+		let (name, default) = match expression.find(":-") {
+			Some(separator) => (&expression[..separator], &expression[separator + 2..]),
+			None => (expression, ""),
+		};
+		match env::var(name) {
+			Ok(value) if !value.is_empty() => result.push_str(&value),
+			_ => result.push_str(default),
+		}
+
+		remaining = &remaining[start + 2 + end + 1..];
+	}
+	result.push_str(remaining);
+
+	result
+}
+
+// Parses a dotenv file. Supports comments, `export` prefixes and quoted
+// values. Single quoted values are taken literally, everything else is interpolated
+pub fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>, String> {
+	let mut envs = vec![];
+
+	for (index, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let line = line
+			.strip_prefix("export ")
+			.map(str::trim_start)
+			.unwrap_or(line);
+
+		let separator = match line.find('=') {
+			Some(separator) => separator,
+			None => return Err(format!("Expected KEY=VALUE on line {}", index + 1)),
+		};
+		let key = line[..separator].trim();
+		if key.is_empty() || key.contains(char::is_whitespace) {
+			return Err(format!("Invalid variable name on line {}", index + 1));
+		}
+
+		let value = line[separator + 1..].trim();
+		let value = if let Some(quoted) = get_quoted(value, '\'') {
+			quoted.to_string()
+		} else if let Some(quoted) = get_quoted(value, '"') {
+			interpolate(&unescape(quoted))
+		} else {
+			// Anything after a # preceded by whitespace is a comment
+			let value = match value.find(" #") {
+				Some(comment) => value[..comment].trim_end(),
+				None => value,
+			};
+			interpolate(value)
+		};
+
+		envs.push((key.to_string(), value));
+	}
+
+	Ok(envs)
+}
+
+// Gets the contents of a quoted value, which may be followed by a comment
+fn get_quoted(value: &str, quote: char) -> Option<&str> {
+	let value = value.strip_prefix(quote)?;
+	let end = value.rfind(quote)?;
+	let rest = value[end + 1..].trim_start();
+	if rest.is_empty() || rest.starts_with('#') {
+		Some(&value[..end])
+	} else {
+		None
+	}
+}
+
+// Replaces the escape sequences in a double quoted value. It's done in a single
+// pass, so that an escaped backslash isn't read as the start of another one
+fn unescape(value: &str) -> String {
+	let mut result = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			result.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => result.push('\n'),
+			Some('"') => result.push('"'),
+			Some('\\') => result.push('\\'),
+			// Anything else isn't an escape sequence, so it's left as is
+			Some(c) => {
+				result.push('\\');
+				result.push(c);
+			}
+			None => result.push('\\'),
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(contents: &str) -> Vec<(String, String)> {
+		parse_env_file(contents).unwrap()
+	}
+
+	fn var(key: &str, value: &str) -> (String, String) {
+		(key.to_string(), value.to_string())
+	}
+
+	#[test]
+	fn interpolates_variables() {
+		env::set_var("GUILLOTINE_TEST_SET", "set");
+		assert_eq!(interpolate("a ${GUILLOTINE_TEST_SET} b"), "a set b");
+		assert_eq!(interpolate("${GUILLOTINE_TEST_UNSET}"), "");
+	}
+
+	#[test]
+	fn interpolates_defaults() {
+		env::set_var("GUILLOTINE_TEST_EMPTY", "");
+		assert_eq!(interpolate("${GUILLOTINE_TEST_UNSET:-default}"), "default");
+		assert_eq!(interpolate("${GUILLOTINE_TEST_EMPTY:-default}"), "default");
+	}
+
+	#[test]
+	fn leaves_unclosed_variables() {
+		assert_eq!(interpolate("a ${NOT_CLOSED"), "a ${NOT_CLOSED");
+	}
+
+	#[test]
+	fn parses_comments_and_exports() {
+		let envs = parse("# comment\n\nexport A=1\nB=2 # comment\nC=a#b");
+		assert_eq!(envs, vec![var("A", "1"), var("B", "2"), var("C", "a#b")]);
+	}
+
+	#[test]
+	fn parses_single_quotes_literally() {
+		let envs = parse(r"A='${HOME} \n' # comment");
+		assert_eq!(envs, vec![var("A", r"${HOME} \n")]);
+	}
+
+	#[test]
+	fn parses_double_quote_escapes() {
+		let envs = parse(r#"A="a\nb \"c\" \t""#);
+		assert_eq!(envs, vec![var("A", "a\nb \"c\" \\t")]);
+	}
+
+	#[test]
+	fn parses_escaped_backslashes() {
+		let envs = parse(r#"A="a\\nb""#);
+		assert_eq!(envs, vec![var("A", r"a\nb")]);
+		let envs = parse(r#"A="a\\\"""#);
+		assert_eq!(envs, vec![var("A", r#"a\""#)]);
+	}
+
+	#[test]
+	fn fails_without_a_value() {
+		assert_eq!(
+			parse_env_file("A=1\nB"),
+			Err(String::from("Expected KEY=VALUE on line 2"))
+		);
+	}
+
+	#[test]
+	fn fails_on_invalid_names() {
+		assert_eq!(
+			parse_env_file("A B=1"),
+			Err(String::from("Invalid variable name on line 1"))
+		);
+	}
+}
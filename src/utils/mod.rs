@@ -1,2 +1,8 @@
 pub mod constants;
+pub mod cron;
+pub mod env;
 pub mod logger;
+pub mod secrets;
+
+#[cfg(target_os = "linux")]
+pub mod procfs;
@@ -0,0 +1,72 @@
+use std::fs;
+
+// A subset of the fields in /proc/[pid]/stat
+pub struct ProcessStat {
+	pub pid: i32,
+	pub state: char,
+	pub ppid: i32,
+	// Resident set size, in bytes
+	pub rss: u64,
+}
+
+pub fn read_stat(pid: i32) -> Option<ProcessStat> {
+	let contents = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+
+	// The process name is wrapped in parentheses and can contain spaces (or
+	// even parentheses), so start parsing after the last ')'
+	let fields_start = contents.rfind(')')?;
+	let mut fields = contents[(fields_start + 1)..].split_whitespace();
+
+	let state = fields.next()?.chars().next()?;
+	let ppid = fields.next()?.parse().ok()?;
+	// rss is the 24th field. We're at the 5th now
+	let rss_pages: u64 = fields.nth(19)?.parse().ok()?;
+
+	Some(ProcessStat {
+		pid,
+		state,
+		ppid,
+		rss: rss_pages * get_page_size(),
+	})
+}
+
+pub fn list_processes() -> Vec<ProcessStat> {
+	let entries = match fs::read_dir("/proc") {
+		Ok(entries) => entries,
+		Err(_) => return vec![],
+	};
+	entries
+		.filter_map(|entry| entry.ok())
+		.filter_map(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+		.filter_map(read_stat)
+		.collect()
+}
+
+// Total resident memory used by a process and all of its descendants, in bytes
+pub fn get_tree_rss(pid: i32) -> u64 {
+	let processes = list_processes();
+
+	let mut tree = vec![pid];
+	let mut rss = 0;
+	let mut index = 0;
+	while index < tree.len() {
+		let parent = tree[index];
+		for process in processes.iter() {
+			if process.pid == parent {
+				rss += process.rss;
+			}
+			if process.ppid == parent {
+				tree.push(process.pid);
+			}
+		}
+		index += 1;
+	}
+	rss
+}
+
+fn get_page_size() -> u64 {
+	match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+		size if size > 0 => size as u64,
+		_ => 4096,
+	}
+}
@@ -0,0 +1,116 @@
+use crate::models::{ModuleRunnerConfig, SecretsStoreConfig};
+use std::{
+	collections::HashMap,
+	fs,
+	path::Path,
+	process::{Command, Stdio},
+};
+
+// Secrets are never shown, or logged. This is shown in their place
+pub const REDACTED: &str = "[redacted]";
+
+// Reads a module's secrets. Errors don't contain any part of the secrets
+pub fn get_secret_envs(
+	config: &ModuleRunnerConfig,
+	working_dir: &str,
+	store: &Option<SecretsStoreConfig>,
+) -> Result<Vec<(String, String)>, String> {
+	let secrets = match &config.secrets {
+		Some(secrets) if !secrets.is_empty() => secrets,
+		_ => return Ok(vec![]),
+	};
+	let mut secrets: Vec<_> = secrets.iter().collect();
+	secrets.sort_by_key(|(name, _)| *name);
+
+	// Only decrypt the store if it's needed, and only once
+	let mut store_secrets: Option<HashMap<String, String>> = None;
+	let mut envs = vec![];
+	for (name, secret) in secrets {
+		let value = match (&secret.file, &secret.store) {
+			(Some(file), None) => {
+				let file = Path::new(working_dir).join(file);
+				let value = fs::read_to_string(&file).map_err(|err| {
+					format!(
+						"Error reading secret '{}' from '{}': {}",
+						name,
+						file.to_string_lossy(),
+						err
+					)
+				})?;
+				// Secret files usually end with a newline, which isn't part of the secret
+				match value.strip_suffix('\n') {
+					Some(value) => value.strip_suffix('\r').unwrap_or(value).to_string(),
+					None => value,
+				}
+			}
+			(None, Some(key)) => {
+				if store_secrets.is_none() {
+					store_secrets = Some(read_store(store)?);
+				}
+				match store_secrets.as_ref().and_then(|secrets| secrets.get(key)) {
+					Some(value) => value.clone(),
+					None => {
+						return Err(format!(
+							"Secret '{}' isn't in the secrets store (as '{}')",
+							name, key
+						))
+					}
+				}
+			}
+			_ => {
+				return Err(format!(
+					"Secret '{}' needs either a `file` or a `store`",
+					name
+				))
+			}
+		};
+		envs.push((name.clone(), value));
+	}
+	Ok(envs)
+}
+
+pub fn uses_store(config: &ModuleRunnerConfig) -> bool {
+	config
+		.secrets
+		.iter()
+		.flatten()
+		.any(|(_, secret)| secret.store.is_some())
+}
+
+fn read_store(store: &Option<SecretsStoreConfig>) -> Result<HashMap<String, String>, String> {
+	let store = store
+		.as_ref()
+		.ok_or_else(|| String::from("There's no secrets_store to read secrets from"))?;
+	let decrypt_command = match &store.decrypt_command {
+		Some(decrypt_command) if !decrypt_command.is_empty() => decrypt_command.clone(),
+		Some(_) => return Err(String::from("The secrets_store's decrypt_command is empty")),
+		None => vec![
+			String::from("gpg"),
+			String::from("--quiet"),
+			String::from("--batch"),
+			String::from("--decrypt"),
+		],
+	};
+
+	let output = Command::new(&decrypt_command[0])
+		.args(&decrypt_command[1..])
+		.arg(&store.path)
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.output()
+		.map_err(|err| format!("Error running '{}': {}", decrypt_command[0], err))?;
+	if !output.status.success() {
+		return Err(format!(
+			"Error decrypting the secrets store '{}': '{}' {}",
+			store.path, decrypt_command[0], output.status
+		));
+	}
+	// The output isn't part of the error, since it could be the secrets
+	serde_json::from_slice(&output.stdout).map_err(|_| {
+		format!(
+			"Expected the decrypted secrets store '{}' to be a JSON object of strings",
+			store.path
+		)
+	})
+}